@@ -0,0 +1,176 @@
+use std::{
+    fs::{File, Metadata},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    fmt::ColType,
+    reader::Dialect,
+    row_index::{read_u64, RowIndex},
+    source::fingerprint,
+};
+
+const MAGIC: &[u8; 8] = b"CSVEXIX2";
+
+/// Index content restored from cache
+pub struct Cached {
+    pub index: RowIndex,
+    pub nb_col: usize,
+    /// Position after the last cached record
+    pub end: u64,
+    /// Column kinds of the first records
+    pub types: Vec<ColType>,
+}
+
+/// On-disk cache of the unfiltered record offsets of a file
+#[derive(Clone)]
+pub struct IndexCache {
+    /// Cache file location
+    path: PathBuf,
+    /// Indexed file location
+    source: PathBuf,
+    /// Dialect used for indexing
+    key: [u8; 8],
+}
+
+impl IndexCache {
+    /// Default cache directory in the user cache dir
+    pub fn default_dir() -> Option<PathBuf> {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .map(|dir| dir.join("csvex"))
+    }
+
+    pub fn new(dir: &Path, source: &Path, dialect: &Dialect, has_header: bool) -> Option<Self> {
+        let source = source.canonicalize().ok()?;
+        let key = [
+            dialect.delimiter,
+            dialect.quote,
+            dialect.escape.unwrap_or(0),
+            dialect.escape.is_some() as u8,
+            dialect.double_quote as u8,
+            dialect.comment.unwrap_or(0),
+            dialect.comment.is_some() as u8,
+            has_header as u8,
+        ];
+        // FNV-1a hash of the file path and dialect
+        let hash = source
+            .to_string_lossy()
+            .as_bytes()
+            .iter()
+            .chain(&key)
+            .fold(0xcbf29ce484222325u64, |hash, b| {
+                (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+            });
+        Some(Self {
+            path: dir.join(format!("{hash:016x}.idx")),
+            source,
+            key,
+        })
+    }
+
+    /// Open cache file and read its header if it still match the file content, which is the
+    /// same size and modification time, or grew with the cached content unchanged
+    fn open(&self) -> io::Result<Option<(BufReader<File>, u64, usize)>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => BufReader::new(file),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        let mut key = [0; 8];
+        file.read_exact(&mut key)?;
+        if &magic != MAGIC || key != self.key {
+            return Ok(None);
+        }
+        let len = read_u64(&mut file)?;
+        let m_time = read_u64(&mut file)?;
+        let end = read_u64(&mut file)?;
+        let nb_col = read_u64(&mut file)? as usize;
+        let mut print = vec![0; read_u64(&mut file)? as usize];
+        file.read_exact(&mut print)?;
+        let metadata = std::fs::metadata(&self.source)?;
+        let is_same = metadata.len() == len && modified(&metadata) == m_time;
+        // Appended content will be indexed
+        let is_appended = metadata.len() > len && fingerprint(&self.source, end)? == print;
+        if !is_same && !is_appended {
+            return Ok(None);
+        }
+        Ok(Some((file, end, nb_col)))
+    }
+
+    /// Load cached index if it still match the file content
+    pub fn load(&self) -> io::Result<Option<Cached>> {
+        let Some((mut file, end, nb_col)) = self.open()? else {
+            return Ok(None);
+        };
+        let mut types = vec![0; read_u64(&mut file)? as usize];
+        file.read_exact(&mut types)?;
+        let types = types
+            .into_iter()
+            .map(ColType::from_byte)
+            .collect::<Option<_>>()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "corrupted types"))?;
+        let index = RowIndex::read(&mut file)?;
+        Ok(Some(Cached {
+            index,
+            nb_col,
+            end,
+            types,
+        }))
+    }
+
+    pub fn store(
+        &self,
+        index: &RowIndex,
+        nb_col: usize,
+        end: u64,
+        types: &[ColType],
+    ) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write in a temporary file then move to never leave a partial cache
+        let tmp = self.path.with_extension("tmp");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&self.key)?;
+        let metadata = std::fs::metadata(&self.source)?;
+        let print = fingerprint(&self.source, end)?;
+        for nb in [
+            metadata.len(),
+            modified(&metadata),
+            end,
+            nb_col as u64,
+            print.len() as u64,
+        ] {
+            file.write_all(&nb.to_le_bytes())?;
+        }
+        file.write_all(&print)?;
+        file.write_all(&(types.len() as u64).to_le_bytes())?;
+        file.write_all(&types.iter().map(|ty| ty.to_byte()).collect::<Vec<_>>())?;
+        index.write(&mut file)?;
+        file.into_inner()?.sync_all()?;
+        std::fs::rename(tmp, &self.path)
+    }
+
+    /// Remove the cache if the file content was not only appended
+    pub fn invalidate_stale(&self) {
+        if !matches!(self.open(), Ok(Some(_))) {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+/// Modification time in nanoseconds since the epoch
+fn modified(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_nanos() as u64)
+}
@@ -60,6 +60,10 @@ impl Cols {
         self.nb_col
     }
 
+    pub fn headers(&self) -> &NestedString {
+        &self.headers
+    }
+
     pub fn get_col(&self, idx: usize) -> (usize, &BStr) {
         let off = self.map[idx];
         (off, self.headers.get(off).unwrap_or_else(|| BStr::new("?")))
@@ -83,6 +87,40 @@ impl Cols {
     }
 
     pub fn set_headers(&mut self, headers: NestedString) {
+        // Match previous columns with new ones by name to keep layout and sizes
+        if self.headers.len() > 0 && headers.len() > 0 {
+            let mut used = vec![false; headers.len()];
+            let remap: Vec<Option<usize>> = (0..self.max_col)
+                .map(|off| match self.headers.get(off) {
+                    Some(name) => {
+                        let pos = headers
+                            .iter()
+                            .enumerate()
+                            .position(|(i, n)| !used[i] && n == name)?;
+                        used[pos] = true;
+                        Some(pos)
+                    }
+                    // Unnamed columns keep their offset if still unnamed
+                    None => (off >= headers.len()).then_some(off),
+                })
+                .collect();
+
+            let mut size = vec![(0, Constraint::Constrained); self.max_col.max(headers.len())];
+            for (off, new) in remap.iter().enumerate() {
+                if let (Some(new), Some(s)) = (new, self.size.get(off)) {
+                    size[*new] = *s;
+                }
+            }
+            self.size = size;
+            self.map = self.map.iter().filter_map(|off| remap[*off]).collect();
+            // New columns are visible
+            for (i, used) in used.into_iter().enumerate() {
+                if !used && !self.map.contains(&i) {
+                    self.map.push(i);
+                }
+            }
+            self.max_col = self.max_col.max(headers.len());
+        }
         self.headers = headers;
     }
 
@@ -1,6 +1,13 @@
+mod complete;
+mod date;
 mod engine;
 mod lexer;
+mod program;
 mod compiler;
+#[cfg(test)]
+mod tests;
 
+pub use complete::{Completion, Expect};
+pub use date::{DateConfig, Tz};
 pub use engine::Engine;
-pub use compiler::{Highlighter, Style, Filter};
+pub use compiler::{Config, Error, Highlighter, Style, Filter};
@@ -1,30 +1,67 @@
-use std::ops::Range;
+use std::{borrow::Cow, collections::HashSet, ops::Range};
 
-use regex::bytes::Regex;
+use aho_corasick::AhoCorasick;
+use bstr::{BStr, ByteSlice};
+use chrono::{Duration, NaiveDateTime};
+use memchr::memmem;
+use regex::bytes::{Regex, RegexBuilder};
 use rust_decimal::Decimal;
 
-use super::lexer::{CmpOp, Lexer, LogiOp, MatchOp, Token, TokenKind};
+use crate::reader::NestedString;
 
-type Result<T> = std::result::Result<T, (Range<usize>, &'static str)>;
+use super::date::{parse_duration, DateConfig};
+use super::engine::fold_case;
+use super::lexer::{
+    is_word, regex_end, ArithOp, CmpOp, Lexer, LogiOp, MatchOp, StrOp, Token, TokenKind,
+};
+use super::program::{self, Op};
+
+/// Error message and its position in the source
+pub type Error = (Range<usize>, Cow<'static, str>);
+type Result<T> = std::result::Result<T, Error>;
 pub type Col = (u32, (u32, u32));
 
-#[derive(Clone)]
+/// Maximum size of a compiled regex and of its lazy DFA cache
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+#[derive(Clone, PartialEq)]
 pub enum Node {
     // Action
-    Exist(Col),
+    Exist(u32),
     Cmp {
-        col: Col,
+        lhs: u32,
         op: CmpOp,
         m: MatchOp,
         range: Range<u32>,
+        nocase: bool,
+    },
+    Pred {
+        lhs: u32,
+        op: StrOp,
+        m: MatchOp,
+        range: Range<u32>,
+        nocase: bool,
+        /// Precompiled search when all values are literals
+        finder: Option<u32>,
+    },
+    In {
+        lhs: u32,
+        set: u32,
+        nocase: bool,
     },
     Match {
-        col: Col,
+        lhs: u32,
         m: MatchOp,
         range: Range<u32>,
     },
+    /// Action evaluated for each column of a selector
+    Each {
+        m: MatchOp,
+        cols: Range<u32>,
+        node: u32,
+    },
     // Logical
-    Unary(bool, u32),
+    Not(u32),
     Binary {
         lhs: u32,
         op: LogiOp,
@@ -32,10 +69,157 @@ pub enum Node {
     },
 }
 
-#[derive(Clone)]
-pub enum Value {
+/// Value expression
+#[derive(Clone, PartialEq)]
+pub enum Expr {
+    Col(Col),
+    /// Current column of the action selector with its range
+    Each((u32, u32)),
     Nb(Decimal),
-    Str(Range<usize>),
+    /// Unquoted string literal
+    Str(Vec<u8>),
+    /// UTC date, with the text of a literal
+    Date(NaiveDateTime, Option<Vec<u8>>),
+    Dur(Duration),
+    /// Record number as displayed
+    Row,
+    /// Record byte offset
+    Offset,
+    Neg(u32),
+    Arith {
+        lhs: u32,
+        op: ArithOp,
+        rhs: u32,
+    },
+    Call {
+        func: Func,
+        args: Vec<u32>,
+    },
+    /// Capture group of a regex match
+    Capture {
+        arg: u32,
+        regex: u32,
+        group: usize,
+    },
+}
+
+/// Literal values of a string predicate
+#[derive(Clone)]
+pub struct Finder {
+    pub(crate) needles: Vec<memmem::Finder<'static>>,
+    /// Search of any needle at once
+    pub(crate) any: Option<AhoCorasick>,
+}
+
+/// Values of an in action
+#[derive(Clone, Default)]
+pub struct ValueSet {
+    pub(crate) strs: HashSet<Vec<u8>>,
+    /// Normalized numbers
+    pub(crate) nbs: HashSet<Decimal>,
+    /// Values that are not literals
+    pub(crate) exprs: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Func {
+    Len,
+    Lower,
+    Upper,
+    Trim,
+    Abs,
+    Round,
+    Substr,
+    Coalesce,
+    IsNum,
+    IsDate,
+    IsBool,
+    IsNull,
+    Fields,
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "len" => Self::Len,
+            "lower" => Self::Lower,
+            "upper" => Self::Upper,
+            "trim" => Self::Trim,
+            "abs" => Self::Abs,
+            "round" => Self::Round,
+            "substr" => Self::Substr,
+            "coalesce" => Self::Coalesce,
+            "isnum" => Self::IsNum,
+            "isdate" => Self::IsDate,
+            "isbool" => Self::IsBool,
+            "isnull" => Self::IsNull,
+            "fields" => Self::Fields,
+            _ => return None,
+        })
+    }
+
+    /// Minimum and maximum number of arguments
+    fn arity(self) -> (usize, usize) {
+        match self {
+            Func::Len
+            | Func::Lower
+            | Func::Upper
+            | Func::Trim
+            | Func::Abs
+            | Func::IsNum
+            | Func::IsDate
+            | Func::IsBool
+            | Func::IsNull => (1, 1),
+            Func::Fields => (0, 0),
+            Func::Round => (1, 2),
+            Func::Substr => (2, 3),
+            Func::Coalesce => (1, usize::MAX),
+        }
+    }
+
+    /// Arguments following the first one are literal parameters for some functions
+    fn arg_side(self, i: usize, side: Side) -> Side {
+        match self {
+            Func::Round | Func::Substr if i > 0 => Side::Value,
+            _ => side,
+        }
+    }
+}
+
+/// Signature and description of the functions, shown while typing a call
+pub(super) const SIGNATURES: &[(&str, &str)] = &[
+    ("len", "len(x): number of characters"),
+    ("lower", "lower(x): lowercase content"),
+    ("upper", "upper(x): uppercase content"),
+    ("trim", "trim(x): content without surrounding whitespace"),
+    ("abs", "abs(x): absolute value"),
+    (
+        "round",
+        "round(x, digits): rounded number, digits defaults to 0",
+    ),
+    (
+        "substr",
+        "substr(x, start, len): characters from start, len is optional",
+    ),
+    ("coalesce", "coalesce(x, y, ...): first non-empty argument"),
+    (
+        "capture",
+        "capture(x, /re/, name): named capture group of a regex match",
+    ),
+    ("isnum", "isnum(x): content is a number"),
+    ("isdate", "isdate(x): content is a date"),
+    ("isbool", "isbool(x): content is a boolean"),
+    ("isnull", "isnull(x): content is empty or a null token"),
+    ("fields", "fields(): number of fields of the row"),
+];
+
+/// How operands are interpreted
+#[derive(Clone, Copy)]
+enum Side {
+    /// Left of an action, numbers and names are columns
+    Col,
+    /// Right of an action, numbers and strings are literals
+    Value,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -46,22 +230,109 @@ pub enum Style {
     Str,
     Regex,
     Action,
+    Func,
     Logi,
 }
 
+/// Resolve a column name against headers, exact match first then case-insensitive
+pub(super) fn resolve_name(headers: &NestedString, token: &Token) -> Result<u32> {
+    let name = match token.kind {
+        TokenKind::Str => token.str.trim_matches('"'),
+        _ => token.str.strip_prefix('$').unwrap_or(token.str),
+    };
+    let lower = name.to_lowercase();
+    for exact in [true, false] {
+        let mut matches = headers.iter().enumerate().filter(|(_, h)| {
+            if exact {
+                h.as_bytes() == name.as_bytes()
+            } else {
+                h.to_str_lossy().to_lowercase() == lower
+            }
+        });
+        match (matches.next(), matches.next()) {
+            (Some((idx, _)), None) => return Ok(idx as u32),
+            (Some(_), Some(_)) => return Err((token.span.clone(), "Ambiguous column name".into())),
+            (None, _) => {}
+        }
+    }
+    Err((token.span.clone(), "No column with this name".into()))
+}
+
+/// Check if a value token reference a column, using $ or the exact name of a header
+fn is_col_value(headers: &NestedString, token: &Token) -> bool {
+    token.kind == TokenKind::Id
+        && (token.str.starts_with('$')
+            || matches!(resolve_name(headers, token), Ok(idx) if headers.get(idx as usize) == Some(token.str.into())))
+}
+
+/// Check if the parenthesis at the lexer position groups an arithmetic operand rather than a
+/// logical expression, by looking at the token following the matching one
+fn is_arith_group(lexer: &Lexer) -> bool {
+    let mut lexer = lexer.clone();
+    let mut depth = 0;
+    loop {
+        match lexer.next().kind {
+            TokenKind::OpenExpr => depth += 1,
+            TokenKind::CloseExpr if depth == 1 => break,
+            TokenKind::CloseExpr => depth -= 1,
+            // Regex literals may contain parentheses
+            TokenKind::Matches | TokenKind::OpenList | TokenKind::SepList => {
+                lexer.regex();
+            }
+            TokenKind::Eof => return false,
+            _ => {}
+        }
+    }
+    match lexer.next().kind {
+        TokenKind::Arith(_)
+        | TokenKind::Cmp(_)
+        | TokenKind::Matches
+        | TokenKind::Pred(_)
+        | TokenKind::In
+        | TokenKind::NoCase => true,
+        TokenKind::Not => lexer.peek().kind == TokenKind::In,
+        _ => false,
+    }
+}
+
+/// Remove the escaping of / in a regex literal, with the positions of the unescaped ones
+fn unescape_slashes(str: &str) -> (String, Vec<usize>) {
+    let mut pattern = String::with_capacity(str.len());
+    let mut slashes = Vec::new();
+    let mut chars = str.chars();
+    while let Some(c) = chars.next() {
+        pattern.push(c);
+        if c == '\\' {
+            match chars.next() {
+                Some('/') => {
+                    pattern.pop();
+                    slashes.push(pattern.len());
+                    pattern.push('/');
+                }
+                Some(c) => pattern.push(c),
+                None => {}
+            }
+        }
+    }
+    (pattern, slashes)
+}
+
+/// Style filter source using the compiler, styling stops at the first error
 pub struct Highlighter {
     styles: Vec<(usize, Style)>,
     idx: usize,
 }
 
 impl Highlighter {
-    pub fn new(source: &str) -> Self {
-        let mut tmp = Self {
-            styles: vec![(0, Style::None)],
+    pub fn new(source: &str, headers: &NestedString, nb_col: usize) -> Self {
+        let config = Config::default();
+        let mut compiler = Compiler::new(source, headers, nb_col, &config);
+        compiler.highlight = true;
+        compiler.parse().ok();
+        Self {
+            styles: compiler.styles,
             idx: 0,
-        };
-        tmp.parse_expr(&mut Lexer::load(source));
-        tmp
+        }
     }
 
     pub fn style(&mut self, pos: usize) -> Style {
@@ -71,150 +342,83 @@ impl Highlighter {
         }
 
         // Move right
-        while self.idx < self.styles.len() && pos >= self.styles[self.idx + 1].0 {
+        while self.idx + 1 < self.styles.len() && pos >= self.styles[self.idx + 1].0 {
             self.idx += 1;
         }
 
         self.styles[self.idx].1
     }
-
-    fn add(&mut self, range: Range<usize>, style: Style) {
-        let last = self.styles.last_mut().unwrap();
-        if last.0 == range.start {
-            last.1 = style;
-        } else {
-            self.styles.push((range.start, style));
-        }
-        self.styles.push((range.end, Style::None));
-    }
-
-    fn parse_range(&mut self, lexer: &mut Lexer) {
-        if let Some(token) = lexer.take_kind(TokenKind::OpenRange) {
-            self.add(token.span, Style::Id);
-            if let Some(token) = lexer.take_kind(TokenKind::Nb) {
-                self.add(token.span, Style::Id);
-            }
-            if let Some(token) = lexer.take_kind(TokenKind::SepRangeLen) {
-                self.add(token.span, Style::Id);
-            }
-            if let Some(token) = lexer.take_kind(TokenKind::Nb) {
-                self.add(token.span, Style::Id);
-            }
-            if let Some(token) = lexer.take_kind(TokenKind::CloseRange) {
-                self.add(token.span, Style::Id);
-            }
-        }
-    }
-
-    fn list(&mut self, lexer: &mut Lexer, parse: impl Fn(&mut Self, &mut Lexer)) {
-        let token = lexer.peek();
-        match token.kind {
-            TokenKind::Match(op) => {
-                lexer.next();
-                Some(op)
-            }
-            _ => None,
-        };
-        lexer.take_kind(TokenKind::OpenList);
-
-        parse(self, lexer);
-
-        while lexer.take_kind(TokenKind::SepList).is_some() {
-            parse(self, lexer)
-        }
-
-        lexer.take_kind(TokenKind::OpenList);
-    }
-
-    fn parse_regex(&mut self, lexer: &mut Lexer) {
-        self.list(lexer, |this, lexer| {
-            if let Some(t) = lexer.take_kind(TokenKind::Str) {
-                this.add(t.span, Style::Regex)
-            }
-        });
-    }
-
-    fn parse_value(&mut self, lexer: &mut Lexer) {
-        self.list(lexer, |this, lexer| {
-            let t = lexer.next();
-            match t.kind {
-                TokenKind::Nb => this.add(t.span, Style::Nb),
-                TokenKind::Str | TokenKind::Id => this.add(t.span, Style::Str),
-                _ => {}
-            }
-        })
-    }
-
-    fn parse_action(&mut self, lexer: &mut Lexer) {
-        let token = lexer.next();
-        if token.kind == TokenKind::Nb {
-            self.add(token.span, Style::Id)
-        }
-        self.parse_range(lexer);
-
-        let token = lexer.peek();
-        match token.kind {
-            TokenKind::Matches => {
-                self.add(token.span.clone(), Style::Action);
-                lexer.next();
-                self.parse_regex(lexer);
-            }
-            TokenKind::Cmp(_) => {
-                self.add(token.span.clone(), Style::Action);
-                lexer.next();
-                self.parse_value(lexer);
-            }
-            _ => {}
-        };
-    }
-
-    fn parse_expr(&mut self, lexer: &mut Lexer) {
-        if lexer.take_kind(TokenKind::Not).is_some() {
-            self.parse_expr(lexer);
-        } else if lexer.take_kind(TokenKind::OpenExpr).is_some() {
-            self.parse_expr(lexer);
-            lexer.take_kind(TokenKind::CloseExpr);
-        } else {
-            self.parse_action(lexer);
-            let token = lexer.next();
-            if let TokenKind::Logi(_) = token.kind {
-                self.add(token.span, Style::Logi);
-            } else if token.kind == TokenKind::Eof {
-                self.add(token.span, Style::None);
-                return;
-            }
-            self.parse_expr(lexer)
-        }
-    }
 }
 
 struct Compiler<'a> {
     filter: Filter,
     lexer: Lexer<'a>,
+    headers: &'a NestedString,
     nb_col: usize,
+    config: &'a Config,
+    /// Column selector of the action being parsed
+    selector: Option<Range<u32>>,
+    /// Only check the syntax, without compiling regexes and literal searches
+    highlight: bool,
+    /// Start position and style of parsed tokens
+    styles: Vec<(usize, Style)>,
 }
 
 impl<'a> Compiler<'a> {
-    fn compile(source: &'a str, nb_col: usize) -> Result<Filter> {
-        let mut compiler = Self {
-            filter: Filter::empty(),
+    fn new(source: &'a str, headers: &'a NestedString, nb_col: usize, config: &'a Config) -> Self {
+        Self {
+            filter: Filter {
+                source: source.to_string(),
+                ..Filter::empty()
+            },
             lexer: Lexer::load(source),
+            headers,
             nb_col,
-        };
-
-        if compiler.lexer.peek().kind != TokenKind::Eof {
-            let start = compiler.parse_expr()?;
-            compiler.filter.start = start;
+            config,
+            selector: None,
+            highlight: false,
+            styles: vec![(0, Style::None)],
         }
+    }
 
-        compiler.filter.source = source.to_string();
+    fn compile(
+        source: &'a str,
+        headers: &'a NestedString,
+        nb_col: usize,
+        config: &'a Config,
+    ) -> Result<Filter> {
+        let mut compiler = Self::new(source, headers, nb_col, config);
+        compiler.parse()?;
+        compiler.filter.config = config.clone();
+        program::build(&mut compiler.filter);
         Ok(compiler.filter)
     }
 
+    fn parse(&mut self) -> Result<()> {
+        if self.lexer.peek().kind != TokenKind::Eof {
+            self.filter.start = self.parse_expr(0)?;
+            let token = self.lexer.next();
+            if token.kind != TokenKind::Eof {
+                return Err((token.span, "Expect && or ||".into()));
+            }
+        }
+        Ok(())
+    }
+
+    fn style(&mut self, range: Range<usize>, style: Style) {
+        let last = self.styles.last_mut().unwrap();
+        if last.0 == range.start {
+            last.1 = style;
+        } else {
+            self.styles.push((range.start, style));
+        }
+        self.styles.push((range.end, Style::None));
+    }
+
     fn expect(&mut self, kind: TokenKind, msg: &'static str) -> Result<Token> {
         let token = self.lexer.next();
         if token.kind != kind {
-            Err((token.span, msg))
+            Err((token.span, msg.into()))
         } else {
             Ok(token)
         }
@@ -226,11 +430,10 @@ impl<'a> Compiler<'a> {
     }
 
     fn parse_range(&mut self) -> Result<(u32, u32)> {
-        let token = self.lexer.peek();
-        if token.kind == TokenKind::OpenRange {
-            self.lexer.next();
+        let lexer = &mut self.lexer;
+        if let Some(open) = lexer.take_kind(TokenKind::OpenRange) {
             let (mut start, mut sep, mut end) = (None, None, None);
-            let mut token = self.lexer.peek();
+            let mut token = lexer.peek();
             let span_start = token.span.start;
             // Parse range start
             if TokenKind::Nb == token.kind {
@@ -238,21 +441,21 @@ impl<'a> Compiler<'a> {
                     token
                         .str
                         .parse::<u32>()
-                        .map_err(|_| (token.span.clone(), "Expect range start"))?,
+                        .map_err(|_| (token.span.clone(), "Expect range start".into()))?,
                 );
-                self.lexer.next();
-                token = self.lexer.peek();
+                lexer.next();
+                token = lexer.peek();
             }
             // Parse range separator
             match token.kind {
                 TokenKind::SepRangeLen => {
-                    self.lexer.next();
-                    token = self.lexer.peek();
+                    lexer.next();
+                    token = lexer.peek();
                     sep = Some(true)
                 }
-                TokenKind::SepRangeEnd => {
-                    self.lexer.next();
-                    token = self.lexer.peek();
+                TokenKind::Arith(ArithOp::Sub) => {
+                    lexer.next();
+                    token = lexer.peek();
                     sep = Some(false)
                 }
                 _ => {}
@@ -263,170 +466,678 @@ impl<'a> Compiler<'a> {
                     token
                         .str
                         .parse::<u32>()
-                        .map_err(|_| (token.span.clone(), "Expect range end"))?,
+                        .map_err(|_| (token.span.clone(), "Expect range end".into()))?,
                 );
-                self.lexer.next();
-                token = self.lexer.peek();
+                lexer.next();
+                token = lexer.peek();
             }
             let span_end = token.span.end;
-            self.expect(TokenKind::CloseRange, "Expect ]")?;
+            let token = lexer.next();
+            if token.kind != TokenKind::CloseRange {
+                return Err((token.span, "Expect ]".into()));
+            }
+            self.style(open.span.start..token.span.end, Style::Id);
             Ok(match (start, sep, end) {
                 (Some(start), None, None) => (start, start + 1),
                 (Some(start), Some(true), Some(len)) => (start, start + len),
                 (Some(start), Some(true), None) => (start, u32::MAX),
                 (None, Some(true), Some(len)) => (0, len),
                 (Some(start), Some(false), Some(end)) if start <= end => (start, end),
-                _ => return Err((span_start..span_end, "Invalid range")),
+                _ => return Err((span_start..span_end, "Invalid range".into())),
             })
         } else {
             Ok((0, u32::MAX))
         }
     }
 
-    fn list<T>(
-        lexer: &mut Lexer,
-        vec: &mut Vec<T>,
-        parse: impl Fn(&mut Lexer) -> Result<T>,
-    ) -> Result<(MatchOp, Range<u32>)> {
-        let token = lexer.peek();
-        let match_op = match token.kind {
+    fn list(&mut self, parse: impl Fn(&mut Self) -> Result<u32>) -> Result<(MatchOp, Range<u32>)> {
+        let match_op = match self.lexer.peek().kind {
             TokenKind::Match(op) => {
-                lexer.next();
+                self.lexer.next();
                 Some(op)
             }
             _ => None,
         };
-        let token = lexer.peek();
+        let token = self.lexer.peek().clone();
         let is_list = if token.kind == TokenKind::OpenList {
-            lexer.next();
+            self.lexer.next();
             true
         } else if match_op.is_some() {
-            return Err((token.span.clone(), "Expect {"));
+            return Err((token.span, "Expect {".into()));
         } else {
             false
         };
 
-        let start = Self::add(vec, parse(lexer)?);
+        let start = parse(self)?;
         let mut end = start;
 
-        while lexer.take_kind(TokenKind::SepList).is_some() {
-            end = Self::add(vec, parse(lexer)?);
+        while self.lexer.take_kind(TokenKind::SepList).is_some() {
+            end = parse(self)?;
         }
         if is_list {
-            let token = lexer.next();
+            let token = self.lexer.next();
             if token.kind != TokenKind::CloseList {
-                return Err((token.span, "Expect }"));
+                return Err((token.span, "Expect }".into()));
             }
         }
 
         Ok((match_op.unwrap_or(MatchOp::All), start..end + 1))
     }
 
-    fn parse_regex(&mut self) -> Result<(MatchOp, Range<u32>)> {
-        Self::list(&mut self.lexer, &mut self.filter.regex, |lexer| {
-            let token = lexer.next();
-            if token.kind == TokenKind::Str || token.kind == TokenKind::Id {
-                Regex::new(token.str.trim_matches('"')).map_err(|_| (token.span, "Invalid regex"))
-            } else {
-                Err((token.span, "Expect regex"))
+    /// Parse a quoted regex or a /pattern/flags literal, compiled with size limits
+    fn regex(&mut self) -> Result<Option<Regex>> {
+        let (token, (pattern, slashes), start, flags) = match self.lexer.regex() {
+            Some(token) => {
+                let end =
+                    regex_end(token.str).ok_or((token.span.clone(), "Expect closing /".into()))?;
+                let flags = &token.str[end + 1..];
+                if let Some(i) = flags.find(|c| !matches!(c, 'i' | 'm' | 's' | 'x')) {
+                    let pos = token.span.start + end + 1 + i;
+                    return Err((
+                        pos..pos + 1,
+                        "Unknown regex flag, expect i, m, s or x".into(),
+                    ));
+                }
+                (
+                    token.clone(),
+                    unescape_slashes(&token.str[1..end]),
+                    token.span.start + 1,
+                    flags,
+                )
+            }
+            None => {
+                let token = self.lexer.next();
+                match token.kind {
+                    TokenKind::Str => (
+                        token.clone(),
+                        (token.str.trim_matches('"').to_string(), vec![]),
+                        token.span.start + 1,
+                        "",
+                    ),
+                    TokenKind::Id => (
+                        token.clone(),
+                        (token.str.to_string(), vec![]),
+                        token.span.start,
+                        "",
+                    ),
+                    _ => return Err((token.span, "Expect regex".into())),
+                }
             }
+        };
+        self.style(token.span.clone(), Style::Regex);
+        // Flags are applied as an inline group
+        let prefix = if flags.is_empty() {
+            String::new()
+        } else {
+            format!("(?{flags})")
+        };
+        let full = format!("{prefix}{pattern}");
+        if let Err(err) = regex_syntax::ParserBuilder::new()
+            .allow_invalid_utf8(true)
+            .build()
+            .parse(&full)
+        {
+            let (span, msg) = match &err {
+                regex_syntax::Error::Parse(err) => (err.span(), err.kind().to_string()),
+                regex_syntax::Error::Translate(err) => (err.span(), err.kind().to_string()),
+                _ => return Err((token.span, "Invalid regex".into())),
+            };
+            let pos = |offset: usize| {
+                let offset = offset.saturating_sub(prefix.len());
+                start + offset + slashes.iter().filter(|i| **i <= offset).count()
+            };
+            let (from, to) = (pos(span.start.offset), pos(span.end.offset));
+            let msg = msg[..1].to_ascii_uppercase() + &msg[1..];
+            return Err((from..to.max(from + 1), msg.into()));
+        }
+        if self.highlight {
+            return Ok(None);
+        }
+        RegexBuilder::new(&full)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .dfa_size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map(Some)
+            .map_err(|err| match err {
+                regex::Error::CompiledTooBig(_) => (token.span, "Regex is too big".into()),
+                _ => (token.span, "Invalid regex".into()),
+            })
+    }
+
+    fn parse_regex(&mut self) -> Result<(MatchOp, Range<u32>)> {
+        self.list(|this| {
+            let regex = this.regex()?;
+            Ok(regex.map_or(0, |regex| Self::add(&mut this.filter.regex, regex)))
         })
     }
 
+    /// Parse the arguments of capture(x, regex, group)
+    fn parse_capture(&mut self, name: Token, side: Side) -> Result<Expr> {
+        self.style(name.span.clone(), Style::Func);
+        self.lexer.next();
+        let arg = self.parse_arith(side)?;
+        self.expect(TokenKind::SepList, "Expect ,")?;
+        let regex = self.regex()?;
+        self.expect(TokenKind::SepList, "Expect ,")?;
+        let token = self.lexer.next();
+        let group = match token.kind {
+            TokenKind::Str | TokenKind::Id => token.str.trim_matches('"'),
+            _ => return Err((token.span, "Expect a capture group name".into())),
+        };
+        self.style(token.span.clone(), Style::Str);
+        self.expect(TokenKind::CloseExpr, "Expect )")?;
+        let Some(regex) = regex else {
+            return Ok(Expr::Capture {
+                arg,
+                regex: 0,
+                group: 0,
+            });
+        };
+        let group = regex
+            .capture_names()
+            .position(|name| name == Some(group))
+            .ok_or((token.span, "No capture group with this name".into()))?;
+        let regex = Self::add(&mut self.filter.regex, regex);
+        Ok(Expr::Capture { arg, regex, group })
+    }
+
     fn parse_value(&mut self) -> Result<(MatchOp, Range<u32>)> {
-        Self::list(&mut self.lexer, &mut self.filter.values, |lexer| {
-            let token = lexer.next();
-            match token.kind {
-                TokenKind::Nb => Ok(Value::Nb(token.str.parse().unwrap())),
-                TokenKind::Str | TokenKind::Id => Ok(Value::Str(token.span)),
-                _ => Err((token.span, "Expect a value")),
-            }
+        self.list(|this| {
+            let expr = this.parse_arith(Side::Value)?;
+            Ok(Self::add(&mut this.filter.values, expr))
         })
     }
-    fn parse_col(&mut self) -> Result<Col> {
-        let token = self.lexer.next();
+
+    /// Resolve a column token
+    fn col_id(&mut self, token: &Token) -> Result<u32> {
         let id = match token.kind {
             TokenKind::Nb => {
                 if let Ok(nb) = token.str.parse::<u32>() {
                     if nb as usize >= self.nb_col {
-                        return Err((token.span, "No column with this index"));
+                        return Err((token.span.clone(), "No column with this index".into()));
                     }
                     nb
                 } else {
-                    return Err((token.span, "Expect a column index"));
+                    return Err((token.span.clone(), "Expect a column index".into()));
                 }
             }
-            _ => return Err((token.span, "Expect a column index")),
+            TokenKind::Str | TokenKind::Id => resolve_name(self.headers, token)?,
+            _ => return Err((token.span.clone(), "Expect a column".into())),
         };
+        self.style(token.span.clone(), Style::Id);
+        Ok(id)
+    }
+
+    /// Resolve a column token and parse its optional range
+    fn col(&mut self, token: Token) -> Result<Col> {
+        let id = self.col_id(&token)?;
         let range = self.parse_range()?;
         Ok((id, range))
     }
 
+    /// Parse a column selector, its end is excluded and u32::MAX when open
+    fn parse_selector(&mut self, token: Token) -> Result<Expr> {
+        let cols = match token.kind {
+            TokenKind::Arith(ArithOp::Mul) => {
+                self.style(token.span.clone(), Style::Id);
+                0..u32::MAX
+            }
+            _ => {
+                let start = match token.kind {
+                    TokenKind::SepCols => 0,
+                    _ => {
+                        let start = self.col_id(&token)?;
+                        self.lexer.next();
+                        start
+                    }
+                };
+                let end = match self.lexer.peek().kind {
+                    TokenKind::Nb | TokenKind::Str | TokenKind::Id => {
+                        let token = self.lexer.next();
+                        self.col_id(&token)? + 1
+                    }
+                    _ => u32::MAX,
+                };
+                if start >= end {
+                    return Err((token.span, "Invalid column range".into()));
+                }
+                start..end
+            }
+        };
+        match &self.selector {
+            Some(selector) if *selector != cols => {
+                return Err((token.span, "Only one column selector per action".into()))
+            }
+            _ => self.selector = Some(cols),
+        }
+        Ok(Expr::Each(self.parse_range()?))
+    }
+
+    fn parse_call(&mut self, name: Token, side: Side) -> Result<Expr> {
+        if name.str == "capture" {
+            return self.parse_capture(name, side);
+        }
+        let func =
+            Func::from_name(name.str).ok_or((name.span.clone(), "Unknown function".into()))?;
+        self.style(name.span.clone(), Style::Func);
+        self.lexer.next();
+        let mut args = Vec::new();
+        if self.lexer.peek().kind != TokenKind::CloseExpr {
+            loop {
+                args.push(self.parse_arith(func.arg_side(args.len(), side))?);
+                if self.lexer.take_kind(TokenKind::SepList).is_none() {
+                    break;
+                }
+            }
+        }
+        let end = self.expect(TokenKind::CloseExpr, "Expect )")?.span.end;
+        let (min, max) = func.arity();
+        if args.len() < min || args.len() > max {
+            return Err((name.span.start..end, "Wrong number of arguments".into()));
+        }
+        Ok(Expr::Call { func, args })
+    }
+
+    fn parse_operand(&mut self, side: Side) -> Result<u32> {
+        let token = self.lexer.next();
+        let expr = match token.kind {
+            TokenKind::Arith(ArithOp::Sub) => {
+                let idx = self.parse_operand(side)?;
+                match &mut self.filter.exprs[idx as usize] {
+                    // Fold negative literals
+                    Expr::Nb(nb) => {
+                        *nb = -*nb;
+                        return Ok(idx);
+                    }
+                    _ => Expr::Neg(idx),
+                }
+            }
+            TokenKind::OpenExpr => {
+                let idx = self.parse_arith(side)?;
+                self.expect(TokenKind::CloseExpr, "Expect )")?;
+                return Ok(idx);
+            }
+            TokenKind::Id if self.lexer.peek().kind == TokenKind::OpenExpr => {
+                self.parse_call(token, side)?
+            }
+            TokenKind::Date => {
+                self.style(token.span.clone(), Style::Nb);
+                let date = self.config.dates.parse(token.str);
+                let date = date.ok_or((token.span, "Invalid date".into()))?;
+                Expr::Date(date, Some(token.str.as_bytes().to_vec()))
+            }
+            TokenKind::Duration => {
+                self.style(token.span.clone(), Style::Nb);
+                Expr::Dur(parse_duration(token.str).ok_or((token.span, "Invalid duration".into()))?)
+            }
+            TokenKind::Id if token.str == "#row" || token.str == "#offset" => {
+                self.style(token.span.clone(), Style::Id);
+                if token.str == "#row" {
+                    Expr::Row
+                } else {
+                    Expr::Offset
+                }
+            }
+            _ => match side {
+                Side::Col
+                    if token.kind == TokenKind::Arith(ArithOp::Mul)
+                        || token.kind == TokenKind::SepCols
+                        || self.lexer.peek().kind == TokenKind::SepCols =>
+                {
+                    self.parse_selector(token)?
+                }
+                Side::Col => Expr::Col(self.col(token)?),
+                Side::Value => match token.kind {
+                    _ if is_col_value(self.headers, &token) => Expr::Col(self.col(token)?),
+                    TokenKind::Nb => {
+                        self.style(token.span.clone(), Style::Nb);
+                        Expr::Nb(
+                            token
+                                .str
+                                .parse()
+                                .map_err(|_| (token.span, "Invalid number".into()))?,
+                        )
+                    }
+                    TokenKind::Id if token.str == "now" || token.str == "today" => {
+                        self.style(token.span.clone(), Style::Nb);
+                        let date = if token.str == "now" {
+                            self.config.dates.now()
+                        } else {
+                            self.config.dates.today()
+                        };
+                        Expr::Date(date, None)
+                    }
+                    TokenKind::Str | TokenKind::Id => {
+                        self.style(token.span.clone(), Style::Str);
+                        Expr::Str(token.str.trim_matches('"').as_bytes().to_vec())
+                    }
+                    _ => return Err((token.span, "Expect a value".into())),
+                },
+            },
+        };
+        Ok(Self::add(&mut self.filter.exprs, expr))
+    }
+
+    /// Parse operands separated by operators of the given precedence level
+    fn parse_arith_level(&mut self, side: Side, mul: bool) -> Result<u32> {
+        let mut lhs = if mul {
+            self.parse_operand(side)?
+        } else {
+            self.parse_arith_level(side, true)?
+        };
+        while let TokenKind::Arith(op) = self.lexer.peek().kind {
+            if matches!(op, ArithOp::Mul | ArithOp::Div | ArithOp::Rem) != mul {
+                break;
+            }
+            self.lexer.next();
+            let rhs = if op == ArithOp::Rem {
+                // Modulo by a literal, as in #row % 100
+                self.parse_operand(Side::Value)?
+            } else if mul {
+                self.parse_operand(side)?
+            } else {
+                self.parse_arith_level(side, true)?
+            };
+            lhs = Self::add(&mut self.filter.exprs, Expr::Arith { lhs, op, rhs });
+        }
+        Ok(lhs)
+    }
+
+    fn parse_arith(&mut self, side: Side) -> Result<u32> {
+        self.parse_arith_level(side, false)
+    }
+
+    fn parse_nocase(&mut self) -> bool {
+        match self.lexer.take_kind(TokenKind::NoCase) {
+            Some(token) => {
+                self.style(token.span, Style::Action);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get the bytes of a literal value
+    fn literal(&self, expr: u32, nocase: bool) -> Option<Vec<u8>> {
+        let bytes = match &self.filter.exprs[expr as usize] {
+            Expr::Str(str) => str.clone(),
+            Expr::Nb(nb) => nb.to_string().into_bytes(),
+            _ => return None,
+        };
+        Some(if nocase { fold_case(&bytes) } else { bytes })
+    }
+
+    /// Precompile the search of literal values
+    fn finder(&mut self, op: StrOp, m: MatchOp, range: Range<u32>, nocase: bool) -> Option<u32> {
+        if self.highlight {
+            return None;
+        }
+        let needles = self.filter.values[range.start as usize..range.end as usize]
+            .iter()
+            .map(|expr| self.literal(*expr, nocase))
+            .collect::<Option<Vec<_>>>()?;
+        let any = (op == StrOp::Contains && m == MatchOp::Any)
+            .then(|| AhoCorasick::new(&needles).ok())
+            .flatten();
+        let needles = needles
+            .iter()
+            .map(|needle| memmem::Finder::new(needle).into_owned())
+            .collect();
+        Some(Self::add(&mut self.filter.finders, Finder { needles, any }))
+    }
+
+    fn parse_in(&mut self, lhs: u32) -> Result<Node> {
+        let nocase = self.parse_nocase();
+        if let TokenKind::Match(_) = self.lexer.peek().kind {
+            return Err((self.lexer.next().span, "Expect {".into()));
+        }
+        let (_, range) = self.parse_value()?;
+        let mut set = ValueSet::default();
+        if !self.highlight {
+            for expr in &self.filter.values[range.start as usize..range.end as usize] {
+                if let Expr::Nb(nb) = self.filter.exprs[*expr as usize] {
+                    set.nbs.insert(nb.normalize());
+                } else if let Some(bytes) = self.literal(*expr, nocase) {
+                    set.strs.insert(bytes);
+                } else {
+                    set.exprs.push(*expr);
+                }
+            }
+        }
+        let set = Self::add(&mut self.filter.sets, set);
+        Ok(Node::In { lhs, set, nocase })
+    }
+
     fn parse_action(&mut self) -> Result<u32> {
-        let col = self.parse_col()?;
-        let token = self.lexer.peek();
+        // Any or all columns of a selector
+        let m = match self.lexer.peek().kind {
+            TokenKind::Match(m) => {
+                let token = self.lexer.next();
+                self.style(token.span.clone(), Style::Action);
+                Some((m, token.span))
+            }
+            _ => None,
+        };
+        self.selector = None;
+        let lhs = self.parse_arith(Side::Col)?;
+        let token = self.lexer.peek().clone();
         let node = match token.kind {
             TokenKind::Matches => {
+                self.style(token.span, Style::Action);
                 self.lexer.next();
                 let (m, range) = self.parse_regex()?;
-                Node::Match { col, m, range }
+                Node::Match { lhs, m, range }
             }
             TokenKind::Cmp(op) => {
+                self.style(token.span, Style::Action);
                 self.lexer.next();
+                let nocase = self.parse_nocase();
                 let (m, range) = self.parse_value()?;
-                Node::Cmp { col, op, m, range }
+                Node::Cmp {
+                    lhs,
+                    op,
+                    m,
+                    range,
+                    nocase,
+                }
             }
-            _ => Node::Exist(col),
+            TokenKind::Pred(op) => {
+                self.style(token.span, Style::Action);
+                self.lexer.next();
+                let nocase = self.parse_nocase();
+                let (m, range) = self.parse_value()?;
+                let finder = self.finder(op, m, range.clone(), nocase);
+                Node::Pred {
+                    lhs,
+                    op,
+                    m,
+                    range,
+                    nocase,
+                    finder,
+                }
+            }
+            TokenKind::In => {
+                self.style(token.span, Style::Action);
+                self.lexer.next();
+                self.parse_in(lhs)?
+            }
+            TokenKind::Not if self.is_not_in() => {
+                for _ in 0..2 {
+                    let token = self.lexer.next();
+                    self.style(token.span, Style::Action);
+                }
+                let node = self.parse_in(lhs)?;
+                let idx = Self::add(&mut self.filter.nodes, node);
+                Node::Not(idx)
+            }
+            _ => Node::Exist(lhs),
         };
-        Ok(Self::add(&mut self.filter.nodes, node))
+        let node = Self::add(&mut self.filter.nodes, node);
+        match (self.selector.take(), m) {
+            (Some(cols), m) => {
+                let m = m.map_or(MatchOp::Any, |(m, _)| m);
+                Ok(Self::add(
+                    &mut self.filter.nodes,
+                    Node::Each { m, cols, node },
+                ))
+            }
+            (None, Some((_, span))) => Err((span, "Expect a column selector".into())),
+            (None, None) => Ok(node),
+        }
     }
 
-    fn parse_expr(&mut self) -> Result<u32> {
+    /// Check if the next tokens are not in
+    fn is_not_in(&self) -> bool {
+        let mut lexer = self.lexer.clone();
+        lexer.next();
+        lexer.peek().kind == TokenKind::In
+    }
+
+    /// Parse a negation, a group or an action
+    fn parse_unary(&mut self) -> Result<u32> {
         if self.lexer.take_kind(TokenKind::Not).is_some() {
-            let idx = self.parse_expr()?;
-            Ok(Self::add(&mut self.filter.nodes, Node::Unary(true, idx)))
-        } else if self.lexer.take_kind(TokenKind::OpenExpr).is_some() {
-            let idx = self.parse_expr()?;
+            let idx = self.parse_unary()?;
+            Ok(Self::add(&mut self.filter.nodes, Node::Not(idx)))
+        } else if self.lexer.peek().kind == TokenKind::OpenExpr && !is_arith_group(&self.lexer) {
+            self.lexer.next();
+            let idx = self.parse_expr(0)?;
             self.expect(TokenKind::CloseExpr, "Expect )")?;
-            Ok(Self::add(&mut self.filter.nodes, Node::Unary(true, idx)))
+            Ok(idx)
         } else {
-            let lhs = self.parse_action()?;
-            let token = self.lexer.peek();
-            let node = if let TokenKind::Logi(op) = token.kind {
-                self.lexer.next();
-                let rhs = self.parse_expr()?;
-                Node::Binary { lhs, op, rhs }
-            } else if TokenKind::Eof == token.kind {
-                Node::Unary(false, lhs)
-            } else {
-                return Err((token.span.clone(), "Expect && or ||"));
+            self.parse_action()
+        }
+    }
+
+    /// Parse logical operations by precedence climbing, binding tighter than min_prec
+    fn parse_expr(&mut self, min_prec: u8) -> Result<u32> {
+        let mut lhs = self.parse_unary()?;
+        while let TokenKind::Logi(op) = self.lexer.peek().kind {
+            let prec = match op {
+                LogiOp::Or => 0,
+                LogiOp::And => 1,
             };
-            Ok(Self::add(&mut self.filter.nodes, node))
+            if prec < min_prec {
+                break;
+            }
+            let token = self.lexer.next();
+            self.style(token.span, Style::Logi);
+            let rhs = self.parse_expr(prec + 1)?;
+            lhs = Self::add(&mut self.filter.nodes, Node::Binary { lhs, op, rhs });
         }
+        Ok(lhs)
+    }
+}
+
+/// Null content when none is configured
+const DEFAULT_NULLS: [&str; 6] = ["null", "na", "n/a", "nan", "none", "nil"];
+
+/// Filter evaluation settings
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub dates: DateConfig,
+    /// Content considered null besides empty content, ignoring ASCII case
+    nulls: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new(DateConfig::default(), vec![])
+    }
+}
+
+impl Config {
+    pub fn new(dates: DateConfig, nulls: Vec<String>) -> Self {
+        let nulls = if nulls.is_empty() {
+            DEFAULT_NULLS.map(String::from).to_vec()
+        } else {
+            nulls
+        };
+        Self { dates, nulls }
+    }
+
+    pub fn is_null(&self, str: &BStr) -> bool {
+        let str = str.trim();
+        str.is_empty()
+            || self
+                .nulls
+                .iter()
+                .any(|null| str.eq_ignore_ascii_case(null.as_bytes()))
     }
 }
 
 #[derive(Clone)]
 pub struct Filter {
-    pub(crate) values: Vec<Value>,
+    pub(crate) exprs: Vec<Expr>,
+    /// Compared expressions
+    pub(crate) values: Vec<u32>,
     pub(crate) regex: Vec<Regex>,
+    pub(crate) finders: Vec<Finder>,
+    pub(crate) sets: Vec<ValueSet>,
     pub(crate) nodes: Vec<Node>,
+    pub(crate) program: Vec<Op>,
+    /// Literals that must be in a matching record
+    pub(crate) prefilter: Vec<Finder>,
     pub(crate) source: String,
     pub(crate) start: u32,
+    pub(crate) config: Config,
 }
 
 impl Filter {
     pub fn empty() -> Self {
         Self {
+            exprs: vec![],
             values: vec![],
             regex: vec![],
+            finders: vec![],
+            sets: vec![],
             nodes: vec![],
+            program: vec![],
+            prefilter: vec![],
             source: String::new(),
             start: 0,
+            config: Config::default(),
+        }
+    }
+
+    pub fn new(
+        source: &str,
+        headers: &NestedString,
+        nb_col: usize,
+        config: &Config,
+    ) -> Result<Self> {
+        Compiler::compile(source, headers, nb_col, config)
+    }
+
+    /// Check if this filter is another one and something else, matching a subset of its rows
+    pub fn narrows(&self, other: &Filter) -> bool {
+        let rest = match self.source.strip_prefix(other.source.as_str()) {
+            Some(rest) if !other.nodes.is_empty() => rest.trim_start(),
+            _ => return false,
+        };
+        if !(rest.starts_with("&&") || is_word(rest, "and"))
+            || self.nodes.get(..other.nodes.len()) != Some(&other.nodes)
+            || self.exprs.get(..other.exprs.len()) != Some(&other.exprs)
+            || self.values.get(..other.values.len()) != Some(&other.values)
+        {
+            return false;
+        }
+        // The other filter must be the first operand of the top-level and
+        let mut node = self.start;
+        loop {
+            match &self.nodes[node as usize] {
+                _ if node == other.start => return true,
+                Node::Binary {
+                    lhs,
+                    op: LogiOp::And,
+                    ..
+                } => node = *lhs,
+                _ => return false,
+            }
         }
     }
 
-    pub fn new(source: &str, nb_col: usize) -> Result<Self> {
-        Compiler::compile(source, nb_col)
+    /// Check if the filter depends on the record number
+    pub fn use_row(&self) -> bool {
+        self.exprs.iter().any(|expr| matches!(expr, Expr::Row))
     }
 }
@@ -0,0 +1,243 @@
+use std::ops::Range;
+
+use bstr::{BStr, BString, ByteSlice};
+
+use super::{
+    compiler::{resolve_name, SIGNATURES},
+    lexer::{Lexer, Token, TokenKind},
+};
+use crate::reader::NestedString;
+
+/// What the token at the cursor is expected to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expect {
+    /// A column, a function or a keyword
+    Operand,
+    Operator,
+    /// A value for an action, with the offset of its column when it is a single one
+    Value(Option<usize>),
+}
+
+/// Completion of the token at the cursor, only looking at the tokens before it without parsing
+/// them to stay useful on the incomplete filters being typed
+#[derive(Debug, Clone)]
+pub struct Completion {
+    /// Span of the partial token at the cursor, empty between tokens
+    pub span: Range<usize>,
+    pub expect: Expect,
+    /// Signature of the function or the operator at the cursor
+    pub hint: Option<&'static str>,
+}
+
+/// Group opened before the cursor
+#[derive(Clone, Copy)]
+enum Group<'a> {
+    Call(&'a str),
+    Expr,
+    List,
+    Range,
+}
+
+const OPERATORS: [&str; 15] = [
+    "==",
+    "!=",
+    ">",
+    "<",
+    ">=",
+    "<=",
+    "~",
+    "matches",
+    "contains",
+    "startswith",
+    "endswith",
+    "in",
+    "not in",
+    "and",
+    "or",
+];
+const KEYWORDS: [&str; 5] = ["not", "any", "all", "#row", "#offset"];
+
+impl Completion {
+    pub fn at(source: &str, cursor: usize, headers: &NestedString, nb_col: usize) -> Self {
+        let mut lexer = Lexer::load(&source[..cursor]);
+        let mut groups = Vec::new();
+        let (mut before, mut last): (Option<Token>, Option<Token>) = (None, None);
+        // Column of the last action and if a value is expected
+        let (mut col, mut after_op) = (None, false);
+        loop {
+            let token = match &last {
+                Some(token) if token.kind == TokenKind::Matches => {
+                    lexer.regex().unwrap_or_else(|| lexer.next())
+                }
+                _ => lexer.next(),
+            };
+            match token.kind {
+                TokenKind::Eof => break,
+                TokenKind::OpenExpr => groups.push(match &last {
+                    Some(name)
+                        if name.kind == TokenKind::Id && name.span.end == token.span.start =>
+                    {
+                        Group::Call(name.str)
+                    }
+                    _ => Group::Expr,
+                }),
+                TokenKind::OpenList => groups.push(Group::List),
+                TokenKind::OpenRange => groups.push(Group::Range),
+                TokenKind::CloseExpr | TokenKind::CloseList | TokenKind::CloseRange => {
+                    groups.pop();
+                }
+                TokenKind::Cmp(_) | TokenKind::Pred(_) | TokenKind::In => {
+                    col = last.as_ref().and_then(|lhs| column(headers, nb_col, lhs));
+                    after_op = true;
+                }
+                TokenKind::Matches => {
+                    col = None;
+                    after_op = true;
+                }
+                TokenKind::NoCase | TokenKind::Match(_) => {}
+                _ => after_op = false,
+            }
+            before = last.replace(token);
+        }
+
+        // The last token is completed if the cursor is at its end
+        let (span, ctx) = match last {
+            Some(token) if token.span.end == cursor && is_word(token.str) => {
+                (token.span.clone(), before)
+            }
+            last => (cursor..cursor, last),
+        };
+        let expect = match ctx.as_ref().map(|token| token.kind) {
+            Some(TokenKind::Cmp(_) | TokenKind::Pred(_) | TokenKind::In | TokenKind::Matches)
+            | Some(TokenKind::NoCase | TokenKind::OpenList) => Expect::Value(col),
+            Some(TokenKind::Match(_)) if after_op => Expect::Value(col),
+            Some(TokenKind::SepList) => match groups.last() {
+                Some(Group::List) => Expect::Value(col),
+                _ => Expect::Operand,
+            },
+            Some(
+                TokenKind::Id
+                | TokenKind::Nb
+                | TokenKind::Str
+                | TokenKind::Date
+                | TokenKind::Duration
+                | TokenKind::Regex
+                | TokenKind::CloseExpr
+                | TokenKind::CloseRange
+                | TokenKind::CloseList,
+            ) => Expect::Operator,
+            _ => Expect::Operand,
+        };
+
+        // Hint the function being named, then the operator, then the enclosing call
+        let word = &source[span.clone()];
+        let signature = |name: &str| {
+            SIGNATURES
+                .iter()
+                .find_map(|(func, signature)| (*func == name).then_some(*signature))
+        };
+        let hint = signature(word)
+            .or_else(|| match expect {
+                Expect::Operator => op_hint(Lexer::load(word).next().kind),
+                _ => None,
+            })
+            .or_else(|| ctx.and_then(|token| op_hint(token.kind)))
+            .or_else(|| {
+                groups.iter().rev().find_map(|group| match group {
+                    Group::Call(name) => signature(name),
+                    _ => None,
+                })
+            });
+        Self { span, expect, hint }
+    }
+
+    /// Candidates starting like the partial token, values are the frequent contents of the
+    /// compared column
+    pub fn candidates(
+        &self,
+        source: &str,
+        headers: &NestedString,
+        nb_col: usize,
+        values: &[BString],
+    ) -> Vec<String> {
+        let prefix = normalize(&source[self.span.clone()]);
+        let mut candidates: Vec<String> = match self.expect {
+            Expect::Operand => {
+                let names = headers.iter().filter_map(quote_name);
+                // Listing every index is only useful when one is being typed
+                let is_index = !prefix.is_empty() && prefix.bytes().all(|c| c.is_ascii_digit());
+                let indexes = (0..if is_index { nb_col } else { 0 }).map(|i| i.to_string());
+                let funcs = SIGNATURES.iter().map(|(name, _)| format!("{name}("));
+                let keywords = KEYWORDS.iter().map(|keyword| keyword.to_string());
+                indexes.chain(names).chain(funcs).chain(keywords).collect()
+            }
+            Expect::Operator => OPERATORS.iter().map(|op| op.to_string()).collect(),
+            Expect::Value(_) => values
+                .iter()
+                .filter_map(|value| quote_value(value.as_bstr()))
+                .collect(),
+        };
+        candidates.retain(|candidate| normalize(candidate).starts_with(&prefix));
+        candidates
+    }
+}
+
+/// Resolve the column of a single column token
+fn column(headers: &NestedString, nb_col: usize, token: &Token) -> Option<usize> {
+    match token.kind {
+        TokenKind::Nb => token.str.parse().ok().filter(|idx| *idx < nb_col),
+        TokenKind::Id if token.str.starts_with('#') => None,
+        TokenKind::Id | TokenKind::Str => resolve_name(headers, token).ok().map(|idx| idx as usize),
+        _ => None,
+    }
+}
+
+fn op_hint(kind: TokenKind) -> Option<&'static str> {
+    Some(match kind {
+        TokenKind::Cmp(_) => "x == value, or any and all of {a, b}, i after it ignores case",
+        TokenKind::Matches => "x ~ /regex/flags, flags among i, m, s and x",
+        TokenKind::Pred(_) => "x contains value, or any and all of {a, b}, i after it ignores case",
+        TokenKind::In => "x in {a, b}, i after it ignores case",
+        TokenKind::NoCase => "case-insensitive operator",
+        TokenKind::Match(_) => "any or all of the columns 3..8, or of the values {a, b}",
+        _ => return None,
+    })
+}
+
+/// Check if a token can be completed
+fn is_word(str: &str) -> bool {
+    str.starts_with(|c: char| c.is_alphanumeric() || matches!(c, '_' | '$' | '#' | '"'))
+}
+
+/// Token content compared with candidates
+fn normalize(str: &str) -> String {
+    str.trim_start_matches(['"', '$']).to_lowercase()
+}
+
+/// Check if a str is lexed as a single token of this kind
+fn is_token(str: &str, kind: TokenKind) -> bool {
+    let token = Lexer::load(str).next();
+    token.kind == kind && token.span == (0..str.len())
+}
+
+/// Reference a column name as the compiler resolves it
+fn quote_name(name: &BStr) -> Option<String> {
+    let name = name.to_str().ok()?;
+    if is_token(name, TokenKind::Id) && !name.starts_with(['#', '$']) {
+        Some(name.to_string())
+    } else if is_token(name, TokenKind::Nb) {
+        Some(format!("${name}"))
+    } else {
+        (!name.is_empty() && !name.contains('"')).then(|| format!("\"{name}\""))
+    }
+}
+
+/// Write a value as a literal, numbers and dates are kept as is
+fn quote_value(value: &BStr) -> Option<String> {
+    let value = value.to_str().ok()?;
+    if is_token(value, TokenKind::Nb) || is_token(value, TokenKind::Date) {
+        Some(value.to_string())
+    } else {
+        (!value.is_empty() && !value.contains('"')).then(|| format!("\"{value}\""))
+    }
+}
@@ -0,0 +1,132 @@
+use chrono::{
+    DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc,
+};
+
+/// Formats tried after user ones
+const DEFAULT_FORMATS: [&str; 8] = [
+    "%Y-%m-%d",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+    "%d/%m/%Y",
+    "%d/%m/%Y %H:%M:%S",
+    "%d/%m/%Y %H:%M",
+];
+
+/// Time zone of dates without offset
+#[derive(Debug, Clone, Copy)]
+pub enum Tz {
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl Tz {
+    /// Parse local, utc or a fixed offset like +02:00
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        let err = || format!("expected local, utc or an offset like +02:00, got '{arg}'");
+        let (sign, offset) = match arg.as_bytes().first() {
+            _ if arg.eq_ignore_ascii_case("local") => return Ok(Self::Local),
+            _ if arg.eq_ignore_ascii_case("utc") || arg == "Z" => {
+                return Ok(Self::Fixed(FixedOffset::east_opt(0).unwrap()))
+            }
+            Some(b'+') => (1, &arg[1..]),
+            Some(b'-') => (-1, &arg[1..]),
+            _ => return Err(err()),
+        };
+        let (hours, minutes) = match offset.split_once(':') {
+            Some(split) => split,
+            None if offset.len() == 4 => offset.split_at(2),
+            None => (offset, "0"),
+        };
+        let (hours, minutes) = (
+            hours.parse::<i32>().map_err(|_| err())?,
+            minutes.parse::<i32>().map_err(|_| err())?,
+        );
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .map(Self::Fixed)
+            .ok_or_else(err)
+    }
+}
+
+/// Date parsing configuration, all dates are compared in UTC
+#[derive(Debug, Clone)]
+pub struct DateConfig {
+    formats: Vec<String>,
+    tz: Tz,
+}
+
+impl Default for DateConfig {
+    fn default() -> Self {
+        Self::new(vec![], Tz::Local)
+    }
+}
+
+impl DateConfig {
+    pub fn new(formats: Vec<String>, tz: Tz) -> Self {
+        Self { formats, tz }
+    }
+
+    /// Parse a date with an offset or in one of the formats
+    pub fn parse(&self, str: &str) -> Option<NaiveDateTime> {
+        let str = str.trim();
+        if let Ok(date) = DateTime::parse_from_rfc3339(str)
+            .or_else(|_| DateTime::parse_from_str(str, "%Y-%m-%dT%H:%M%#z"))
+        {
+            return Some(date.naive_utc());
+        }
+        self.formats
+            .iter()
+            .map(String::as_str)
+            .chain(DEFAULT_FORMATS)
+            .find_map(|fmt| {
+                NaiveDateTime::parse_from_str(str, fmt).ok().or_else(|| {
+                    NaiveDate::parse_from_str(str, fmt)
+                        .ok()
+                        .map(|date| date.and_time(NaiveTime::MIN))
+                })
+            })
+            .and_then(|date| self.to_utc(date))
+    }
+
+    fn to_utc(&self, date: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self.tz {
+            Tz::Local => Local
+                .from_local_datetime(&date)
+                .earliest()
+                .map(|date| date.naive_utc()),
+            Tz::Fixed(offset) => offset
+                .from_local_datetime(&date)
+                .single()
+                .map(|date| date.naive_utc()),
+        }
+    }
+
+    pub fn now(&self) -> NaiveDateTime {
+        Utc::now().naive_utc()
+    }
+
+    /// Start of the current day in the configured time zone
+    pub fn today(&self) -> NaiveDateTime {
+        let date = match self.tz {
+            Tz::Local => Local::now().date_naive(),
+            Tz::Fixed(offset) => Utc::now().with_timezone(&offset).date_naive(),
+        };
+        let midnight = date.and_time(NaiveTime::MIN);
+        self.to_utc(midnight).unwrap_or(midnight)
+    }
+}
+
+/// Parse a duration like 7d, units are s, m, h, d and w
+pub fn parse_duration(str: &str) -> Option<Duration> {
+    let (nb, unit) = str.split_at(str.len() - 1);
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 604800,
+        _ => return None,
+    };
+    Some(Duration::seconds(nb.parse::<u32>().ok()? as i64 * secs))
+}
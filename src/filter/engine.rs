@@ -1,13 +1,16 @@
-use std::{fmt::Display, io::Write, ops::Range};
+use std::{borrow::Cow, cell::Cell, fmt::Display, io::Write, ops::Range};
 
 use bstr::{BStr, ByteSlice};
+use chrono::{Duration, NaiveDateTime};
+use memchr::memmem;
 use rust_decimal::Decimal;
 
-use crate::reader::NestedString;
+use crate::{fmt::Ty, reader::NestedString};
 
 use super::{
-    compiler::{Col, Filter, Node, Value},
-    lexer::{CmpOp, LogiOp, MatchOp},
+    compiler::{Col, Expr, Filter, Finder, Func, Node},
+    lexer::{ArithOp, CmpOp, LogiOp, MatchOp, StrOp},
+    program::Op,
 };
 
 pub fn in_place_str<const N: usize>(array: &mut [u8; N], it: impl Display) -> &str {
@@ -18,13 +21,93 @@ pub fn in_place_str<const N: usize>(array: &mut [u8; N], it: impl Display) -> &s
     std::str::from_utf8(&array[..len]).unwrap()
 }
 
+/// Lowercase bytes for case-insensitive comparisons
+pub fn fold_case(bytes: &[u8]) -> Vec<u8> {
+    bytes.to_str_lossy().to_lowercase().into_bytes()
+}
+
+/// Evaluated expression
+enum Val<'a> {
+    Null,
+    Nb(Decimal),
+    /// Field or function result, numeric if it can be parsed
+    Text(Cow<'a, [u8]>),
+    /// String literal, always compared as bytes
+    Str(Cow<'a, [u8]>),
+    /// UTC date, with its literal text
+    Date(NaiveDateTime, Option<&'a [u8]>),
+    Dur(Duration),
+    Bool(bool),
+}
+
+impl Val<'_> {
+    fn nb(&self) -> Option<Decimal> {
+        match self {
+            Val::Nb(nb) => Some(*nb),
+            Val::Text(str) => {
+                // Skip the parsing of content that cannot be a number
+                let str = str.trim();
+                match str.first() {
+                    Some(b'0'..=b'9' | b'-' | b'+' | b'.') => str.to_str().ok()?.parse().ok(),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        match self {
+            Val::Null => Cow::Borrowed(&[]),
+            Val::Nb(nb) => {
+                let mut buff = [0; 32];
+                Cow::Owned(in_place_str(&mut buff, nb).as_bytes().to_vec())
+            }
+            Val::Text(str) | Val::Str(str) => Cow::Borrowed(str),
+            Val::Date(_, Some(text)) => Cow::Borrowed(text),
+            Val::Date(date, None) => {
+                Cow::Owned(date.format("%Y-%m-%dT%H:%M:%S").to_string().into_bytes())
+            }
+            Val::Dur(dur) => Cow::Owned(dur.num_seconds().to_string().into_bytes()),
+            Val::Bool(bool) => Cow::Borrowed(if *bool { b"true" } else { b"false" }),
+        }
+    }
+
+    fn folded(&self, nocase: bool) -> Cow<'_, [u8]> {
+        let bytes = self.bytes();
+        if nocase {
+            Cow::Owned(fold_case(&bytes))
+        } else {
+            bytes
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Val::Null => true,
+            Val::Nb(_) | Val::Date(..) | Val::Dur(_) => false,
+            // A false predicate is not present
+            Val::Bool(bool) => !bool,
+            Val::Text(str) | Val::Str(str) => str.is_empty(),
+        }
+    }
+}
+
 pub struct Engine<'a> {
     filter: &'a Filter,
+    /// Current column of the action selector
+    current: Cell<u32>,
+    /// Number and byte offset of the checked record
+    pos: Cell<(u64, u64)>,
 }
 
 impl<'r> Engine<'r> {
     pub fn new(filter: &'r Filter) -> Self {
-        Self { filter }
+        Self {
+            filter,
+            current: Cell::new(0),
+            pos: Cell::new((0, 0)),
+        }
     }
 
     fn get_col<'a>(&self, record: &'a NestedString, (idx, (start, end)): &Col) -> &'a BStr {
@@ -32,6 +115,149 @@ impl<'r> Engine<'r> {
         BStr::new(&field[(*start as usize).min(field.len())..(*end as usize).min(field.len())])
     }
 
+    fn eval<'a>(&'a self, record: &'a NestedString, i: u32) -> Val<'a> {
+        match &self.filter.exprs[i as usize] {
+            Expr::Col(col) => Val::Text(Cow::Borrowed(self.get_col(record, col))),
+            Expr::Each(range) => {
+                let col = (self.current.get(), *range);
+                Val::Text(Cow::Borrowed(self.get_col(record, &col)))
+            }
+            Expr::Nb(nb) => Val::Nb(*nb),
+            Expr::Str(str) => Val::Str(Cow::Borrowed(str)),
+            Expr::Date(date, text) => Val::Date(*date, text.as_deref()),
+            Expr::Dur(dur) => Val::Dur(*dur),
+            Expr::Row => Val::Nb((self.pos.get().0 + 1).into()),
+            Expr::Offset => Val::Nb(self.pos.get().1.into()),
+            Expr::Neg(i) => match self.eval(record, *i).nb() {
+                Some(nb) => Val::Nb(-nb),
+                None => Val::Null,
+            },
+            Expr::Arith { lhs, op, rhs } => {
+                let (lhs, rhs) = (self.eval(record, *lhs), self.eval(record, *rhs));
+                let (Some(a), Some(b)) = (lhs.nb(), rhs.nb()) else {
+                    return self.date_arith(&lhs, *op, &rhs);
+                };
+                let result = match op {
+                    ArithOp::Add => a.checked_add(b),
+                    ArithOp::Sub => a.checked_sub(b),
+                    ArithOp::Mul => a.checked_mul(b),
+                    ArithOp::Div => a.checked_div(b),
+                    ArithOp::Rem => a.checked_rem(b),
+                };
+                result.map_or(Val::Null, Val::Nb)
+            }
+            Expr::Call { func, args } => self.call(record, *func, args),
+            Expr::Capture { arg, regex, group } => {
+                let val = self.eval(record, *arg);
+                let bytes = val.bytes();
+                let captures = self.filter.regex[*regex as usize].captures(&bytes);
+                match captures.and_then(|captures| captures.get(*group)) {
+                    Some(group) => Val::Text(Cow::Owned(group.as_bytes().to_vec())),
+                    None => Val::Null,
+                }
+            }
+        }
+    }
+
+    /// Parse a value as a date if possible
+    fn date(&self, val: &Val) -> Option<NaiveDateTime> {
+        match val {
+            Val::Date(date, _) => Some(*date),
+            Val::Text(str) | Val::Str(str) => self.filter.config.dates.parse(str.to_str().ok()?),
+            _ => None,
+        }
+    }
+
+    /// Shift dates by durations and subtract dates
+    fn date_arith<'a>(&self, lhs: &Val, op: ArithOp, rhs: &Val) -> Val<'a> {
+        let result = match (lhs, op, rhs) {
+            (Val::Dur(a), ArithOp::Add, Val::Dur(b)) => a.checked_add(b).map(Val::Dur),
+            (Val::Dur(a), ArithOp::Sub, Val::Dur(b)) => a.checked_sub(b).map(Val::Dur),
+            (Val::Dur(dur), ArithOp::Add, date) | (date, ArithOp::Add, Val::Dur(dur)) => self
+                .date(date)
+                .and_then(|date| date.checked_add_signed(*dur))
+                .map(|date| Val::Date(date, None)),
+            (date, ArithOp::Sub, Val::Dur(dur)) => self
+                .date(date)
+                .and_then(|date| date.checked_sub_signed(*dur))
+                .map(|date| Val::Date(date, None)),
+            (a, ArithOp::Sub, b) => match (self.date(a), self.date(b)) {
+                (Some(a), Some(b)) => Some(Val::Dur(a - b)),
+                _ => None,
+            },
+            _ => None,
+        };
+        result.unwrap_or(Val::Null)
+    }
+
+    fn call<'a>(&'a self, record: &'a NestedString, func: Func, args: &[u32]) -> Val<'a> {
+        let arg = |i: usize| match args.get(i) {
+            Some(i) => self.eval(record, *i),
+            None => Val::Null,
+        };
+        let text = |f: fn(&str) -> String| match arg(0) {
+            Val::Null => Val::Null,
+            val => Val::Text(Cow::Owned(f(&val.bytes().to_str_lossy()).into_bytes())),
+        };
+        match func {
+            Func::Len => match arg(0) {
+                Val::Null => Val::Null,
+                val => Val::Nb(val.bytes().chars().count().into()),
+            },
+            Func::Lower => text(|s| s.to_lowercase()),
+            Func::Upper => text(|s| s.to_uppercase()),
+            Func::Trim => text(|s| s.trim().to_string()),
+            Func::Abs => arg(0).nb().map_or(Val::Null, |nb| Val::Nb(nb.abs())),
+            Func::Round => {
+                let digits = arg(1).nb().unwrap_or_default();
+                match (arg(0).nb(), u32::try_from(digits).ok()) {
+                    (Some(nb), Some(digits)) => Val::Nb(nb.round_dp(digits)),
+                    _ => Val::Null,
+                }
+            }
+            Func::Substr => {
+                let pos = |val: Val| {
+                    val.nb()
+                        .filter(|nb| !nb.is_sign_negative())
+                        .and_then(|nb| usize::try_from(nb.trunc()).ok())
+                };
+                let val = arg(0);
+                let (Some(start), len) = (pos(arg(1)), pos(arg(2))) else {
+                    return Val::Null;
+                };
+                let str: String = val
+                    .bytes()
+                    .chars()
+                    .skip(start)
+                    .take(len.unwrap_or(usize::MAX))
+                    .collect();
+                Val::Text(Cow::Owned(str.into_bytes()))
+            }
+            Func::IsNum | Func::IsDate | Func::IsBool | Func::IsNull => {
+                let config = &self.filter.config;
+                let val = arg(0);
+                let bytes = val.bytes();
+                let str = BStr::new(&bytes);
+                Val::Bool(match func {
+                    Func::IsNum => match val {
+                        Val::Nb(_) => true,
+                        Val::Text(_) => matches!(Ty::guess(str), Ty::Nb { .. }),
+                        _ => false,
+                    },
+                    Func::IsDate => self.date(&val).is_some(),
+                    Func::IsBool => matches!(Ty::guess(str), Ty::Bool),
+                    _ => matches!(val, Val::Null) || config.is_null(str),
+                })
+            }
+            Func::Fields => Val::Nb(record.len().into()),
+            Func::Coalesce => args
+                .iter()
+                .map(|i| self.eval(record, *i))
+                .find(|val| !val.is_empty())
+                .unwrap_or(Val::Null),
+        }
+    }
+
     fn cmp<T: Eq + Ord>(a: T, b: T, op: CmpOp) -> bool {
         match op {
             CmpOp::Eq => a == b,
@@ -43,80 +269,197 @@ impl<'r> Engine<'r> {
         }
     }
 
-    fn check_action(&self, str: &BStr, op: CmpOp, value: &Value) -> bool {
-        match value {
-            Value::Nb(nb) => {
-                if let Some(field) = str.to_str().ok().and_then(|s| s.parse::<Decimal>().ok()) {
-                    Self::cmp(&field, nb, op)
-                } else {
-                    let mut buff = [0; 32];
-                    let nb = in_place_str(&mut buff, nb);
-                    Self::cmp(str, BStr::new(nb), op)
-                }
-            }
-            Value::Str(value) => {
-                let str = str.as_ref();
-                let value = self.filter.source[value.clone()]
-                    .as_bytes()
-                    .trim_with(|c| c == '"');
-                Self::cmp(str, value, op)
+    fn check_action(&self, lhs: &Val, op: CmpOp, value: &Val, nocase: bool) -> bool {
+        match (lhs, value) {
+            (Val::Null, _) | (_, Val::Null) => false,
+            (Val::Dur(a), Val::Dur(b)) => Self::cmp(a, b, op),
+            // Parse the other side as a date, falling back to a string comparison
+            (Val::Date(..), _) | (_, Val::Date(..)) => match (self.date(lhs), self.date(value)) {
+                (Some(a), Some(b)) => Self::cmp(a, b, op),
+                _ => Self::cmp(lhs.folded(nocase), value.folded(nocase), op),
+            },
+            (Val::Str(_), _) | (_, Val::Str(_)) => {
+                Self::cmp(lhs.folded(nocase), value.folded(nocase), op)
             }
+            _ => match (lhs.nb(), value.nb()) {
+                (Some(a), Some(b)) => Self::cmp(a, b, op),
+                _ => Self::cmp(lhs.folded(nocase), value.folded(nocase), op),
+            },
         }
     }
 
     fn compare(
         &self,
         record: &NestedString,
-        col: &Col,
+        lhs: u32,
         op: CmpOp,
         m: MatchOp,
         range: Range<u32>,
+        nocase: bool,
+    ) -> bool {
+        let lhs = self.eval(record, lhs);
+        let mut values = self.filter.values[range.start as usize..range.end as usize].iter();
+        let check = |i: &u32| self.check_action(&lhs, op, &self.eval(record, *i), nocase);
+        match m {
+            MatchOp::All => values.all(check),
+            MatchOp::Any => values.any(check),
+        }
+    }
+
+    fn str_pred(hay: &[u8], op: StrOp, needle: &[u8]) -> bool {
+        match op {
+            StrOp::Contains => memmem::find(hay, needle).is_some(),
+            StrOp::StartsWith => hay.starts_with(needle),
+            StrOp::EndsWith => hay.ends_with(needle),
+        }
+    }
+
+    fn find_literals(hay: &[u8], op: StrOp, m: MatchOp, finder: &Finder) -> bool {
+        if let Some(any) = &finder.any {
+            return any.is_match(hay);
+        }
+        let mut needles = finder.needles.iter();
+        let check = |needle: &memmem::Finder| match op {
+            StrOp::Contains => needle.find(hay).is_some(),
+            _ => Self::str_pred(hay, op, needle.needle()),
+        };
+        match m {
+            MatchOp::All => needles.all(check),
+            MatchOp::Any => needles.any(check),
+        }
+    }
+
+    fn find_values(
+        &self,
+        record: &NestedString,
+        hay: &[u8],
+        op: StrOp,
+        m: MatchOp,
+        range: Range<u32>,
+        nocase: bool,
     ) -> bool {
-        let str = self.get_col(record, col);
         let mut values = self.filter.values[range.start as usize..range.end as usize].iter();
+        let check = |i: &u32| match self.eval(record, *i) {
+            Val::Null => false,
+            value => Self::str_pred(hay, op, &value.folded(nocase)),
+        };
         match m {
-            MatchOp::All => values.all(|value| Self::check_action(self, str, op, value)),
-            MatchOp::Any => values.any(|value| Self::check_action(self, str, op, value)),
+            MatchOp::All => values.all(check),
+            MatchOp::Any => values.any(check),
         }
     }
 
-    fn per_match(&self, record: &NestedString, col: &Col, m: MatchOp, range: Range<u32>) -> bool {
-        let str = self.get_col(record, col);
+    fn in_set(&self, record: &NestedString, lhs: u32, set: u32, nocase: bool) -> bool {
+        let lhs = self.eval(record, lhs);
+        if let Val::Null = lhs {
+            return false;
+        }
+        let set = &self.filter.sets[set as usize];
+        lhs.nb().is_some_and(|nb| set.nbs.contains(&nb.normalize()))
+            || set.strs.contains(&*lhs.folded(nocase))
+            || set
+                .exprs
+                .iter()
+                .any(|i| self.check_action(&lhs, CmpOp::Eq, &self.eval(record, *i), nocase))
+    }
+
+    fn per_match(&self, record: &NestedString, lhs: u32, m: MatchOp, range: Range<u32>) -> bool {
+        let lhs = self.eval(record, lhs);
+        if let Val::Null = lhs {
+            return false;
+        }
+        let str = lhs.bytes();
         let mut regs = self.filter.regex[range.start as usize..range.end as usize].iter();
         match m {
-            MatchOp::All => regs.all(|value| value.is_match(str)),
-            MatchOp::Any => regs.any(|value| value.is_match(str)),
+            MatchOp::All => regs.all(|value| value.is_match(&str)),
+            MatchOp::Any => regs.any(|value| value.is_match(&str)),
         }
     }
 
     fn run_node(&self, record: &NestedString, i: u32) -> bool {
         match &self.filter.nodes[i as usize] {
-            Node::Exist(col) => !self.get_col(record, col).is_empty(),
-            Node::Cmp { col, op, m, range } => self.compare(record, col, *op, *m, range.clone()),
-            Node::Match { col, m, range } => self.per_match(record, col, *m, range.clone()),
-            Node::Unary(inverse, id) => {
-                let result = self.run_node(record, *id);
-                if *inverse {
-                    !result
-                } else {
-                    result
+            Node::Exist(lhs) => !self.eval(record, *lhs).is_empty(),
+            Node::Cmp {
+                lhs,
+                op,
+                m,
+                range,
+                nocase,
+            } => self.compare(record, *lhs, *op, *m, range.clone(), *nocase),
+            Node::Pred {
+                lhs,
+                op,
+                m,
+                range,
+                nocase,
+                finder,
+            } => {
+                let lhs = self.eval(record, *lhs);
+                let hay = lhs.folded(*nocase);
+                match finder {
+                    _ if matches!(lhs, Val::Null) => false,
+                    Some(finder) => {
+                        let finder = &self.filter.finders[*finder as usize];
+                        Self::find_literals(&hay, *op, *m, finder)
+                    }
+                    None => self.find_values(record, &hay, *op, *m, range.clone(), *nocase),
                 }
             }
-            Node::Binary { lhs, op, rhs } => {
-                let (lhs, rhs) = (self.run_node(record, *lhs), self.run_node(record, *rhs));
-                match op {
-                    LogiOp::And => lhs && rhs,
-                    LogiOp::Or => lhs || rhs,
+            Node::In { lhs, set, nocase } => self.in_set(record, *lhs, *set, *nocase),
+            Node::Match { lhs, m, range } => self.per_match(record, *lhs, *m, range.clone()),
+            Node::Each { m, cols, node } => {
+                // Open selectors stop at the last field
+                let end = match cols.end {
+                    u32::MAX => record.len() as u32,
+                    end => end,
+                };
+                let mut cols = cols.start..end;
+                let check = |col| {
+                    self.current.set(col);
+                    self.run_node(record, *node)
+                };
+                match m {
+                    MatchOp::All => cols.all(check),
+                    MatchOp::Any => cols.any(check),
                 }
             }
+            Node::Not(id) => !self.run_node(record, *id),
+            Node::Binary { lhs, op, rhs } => match op {
+                LogiOp::And => self.run_node(record, *lhs) && self.run_node(record, *rhs),
+                LogiOp::Or => self.run_node(record, *lhs) || self.run_node(record, *rhs),
+            },
         }
     }
 
-    pub fn check(&self, record: &NestedString) -> bool {
-        if self.filter.nodes.is_empty() {
-            true
-        } else {
-            self.run_node(record, self.filter.start)
+    /// Check a record given its number from the first row and its byte offset
+    pub fn check(&self, record: &NestedString, row: u64, offset: u64) -> bool {
+        if self.filter.program.is_empty() {
+            return true;
+        }
+        // Skip records missing a required literal before evaluating their fields
+        let raw = record.raw();
+        if !self
+            .filter
+            .prefilter
+            .iter()
+            .all(|finder| Self::find_literals(raw, StrOp::Contains, MatchOp::Any, finder))
+        {
+            return false;
+        }
+        self.pos.set((row, offset));
+        let (mut pc, mut register) = (0, true);
+        while let Some(op) = self.filter.program.get(pc) {
+            match *op {
+                Op::Test(node) => register = self.run_node(record, node),
+                Op::Not => register = !register,
+                Op::Jump { when, to } if register == when => {
+                    pc = to as usize;
+                    continue;
+                }
+                Op::Jump { .. } => {}
+            }
+            pc += 1;
         }
+        register
     }
 }
@@ -9,21 +9,28 @@ It is designed to not allocate any memory. */
 pub enum TokenKind {
     Cmp(CmpOp),
     Logi(LogiOp),
+    Arith(ArithOp),
     Match(MatchOp),
+    Pred(StrOp),
     Matches,     // matches, ~,
+    In,          // in
+    NoCase,      // i directly following an operator
     Not,         // not, !
     OpenExpr,    // (
     CloseExpr,   // )
     OpenRange,   // [
     CloseRange,  // ]
     SepRangeLen, // :
-    SepRangeEnd, // -
+    SepCols,     // ..
     OpenList,    // {
     CloseList,   // }
     SepList,     // ,
     Nb,          // Decimal Number
+    Date,        // ISO 8601 date or datetime
+    Duration,    // Number followed by a unit
     Str,         // surrounded by "
-    Id,          // surrounded by whitespace
+    Regex,       // /pattern/flags
+    Id,          // surrounded by whitespace, may start with $ or #
     Eof,
 }
 
@@ -43,6 +50,22 @@ pub enum LogiOp {
     Or,  // or, ||
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add, // +
+    Sub, // -, also range end separator
+    Mul, // *
+    Div, // /
+    Rem, // %
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrOp {
+    Contains,   // contains
+    StartsWith, // startswith
+    EndsWith,   // endswith
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MatchOp {
     All, // all
@@ -62,6 +85,10 @@ pub struct Lexer<'a> {
     source: &'a str,
     offset: usize,
     peeked: Option<Token<'a>>,
+    /// End of the last operator token
+    op_end: usize,
+    /// If the last token ends an operand, where an operator is expected
+    after_operand: bool,
 }
 
 impl<'a> Lexer<'a> {
@@ -71,6 +98,8 @@ impl<'a> Lexer<'a> {
             source,
             offset: 0,
             peeked: None,
+            op_end: usize::MAX,
+            after_operand: false,
         }
     }
 
@@ -78,12 +107,29 @@ impl<'a> Lexer<'a> {
         self.peeked.take().unwrap_or_else(|| self.lex_next())
     }
 
-    pub fn take_kind(&mut self, kind: TokenKind) -> Option<Token> {
+    pub fn take_kind(&mut self, kind: TokenKind) -> Option<Token<'a>> {
         (self.peek().kind == kind).then(|| self.next())
     }
 
     fn token(&mut self, kind: TokenKind, len: usize) -> Token<'a> {
         self.offset += len;
+        if let TokenKind::Cmp(_) | TokenKind::Pred(_) | TokenKind::In = kind {
+            self.op_end = self.offset;
+        }
+        self.after_operand = match kind {
+            TokenKind::Id
+            | TokenKind::Nb
+            | TokenKind::Str
+            | TokenKind::Date
+            | TokenKind::Duration
+            | TokenKind::Regex
+            | TokenKind::CloseExpr
+            | TokenKind::CloseRange
+            | TokenKind::CloseList => true,
+            // A lone * selects all columns
+            TokenKind::Arith(ArithOp::Mul) => !self.after_operand,
+            _ => false,
+        };
         let span = self.offset - len..self.offset;
         Token {
             kind,
@@ -92,6 +138,25 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Lex a regex literal if the next token starts one, a missing closing / extends it to the end
+    pub fn regex(&mut self) -> Option<Token<'a>> {
+        if self.peek().kind != TokenKind::Arith(ArithOp::Div) {
+            return None;
+        }
+        self.offset = self.peeked.take().unwrap().span.start;
+        let remaining = &self.source[self.offset..];
+        let len = match regex_end(remaining) {
+            Some(end) => {
+                end + 1
+                    + remaining[end + 1..]
+                        .find(|c: char| !c.is_ascii_alphabetic())
+                        .unwrap_or(remaining.len() - end - 1)
+            }
+            None => remaining.len(),
+        };
+        Some(self.token(TokenKind::Regex, len))
+    }
+
     pub fn peek(&mut self) -> &Token<'a> {
         if self.peeked.is_none() {
             self.peeked = Some(self.lex_next());
@@ -100,6 +165,10 @@ impl<'a> Lexer<'a> {
     }
 
     fn lex_next(&mut self) -> Token<'a> {
+        // Case-insensitive modifier
+        if self.offset == self.op_end && is_word(&self.source[self.offset..], "i") {
+            return self.token(TokenKind::NoCase, 1);
+        }
         // Skip whitespace
         let until = self.source[self.offset..]
             .char_indices()
@@ -119,6 +188,7 @@ impl<'a> Lexer<'a> {
                     [b'<', b'='] => TokenKind::Cmp(CmpOp::Le),
                     [b'&', b'&'] => TokenKind::Logi(LogiOp::And),
                     [b'|', b'|'] => TokenKind::Logi(LogiOp::Or),
+                    [b'.', b'.'] => TokenKind::SepCols,
                     _ => TokenKind::Eof,
                 };
                 if kind != TokenKind::Eof {
@@ -140,7 +210,11 @@ impl<'a> Lexer<'a> {
                 b']' => TokenKind::CloseRange,
                 b',' => TokenKind::SepList,
                 b':' => TokenKind::SepRangeLen,
-                b'-' => TokenKind::SepRangeEnd,
+                b'+' => TokenKind::Arith(ArithOp::Add),
+                b'-' => TokenKind::Arith(ArithOp::Sub),
+                b'*' => TokenKind::Arith(ArithOp::Mul),
+                b'/' => TokenKind::Arith(ArithOp::Div),
+                b'%' => TokenKind::Arith(ArithOp::Rem),
                 _ => TokenKind::Eof,
             };
 
@@ -160,16 +234,42 @@ impl<'a> Lexer<'a> {
                     self.token(TokenKind::Str, len)
                 }
                 c if c.is_ascii_digit() => {
-                    let len = chars
-                        .find_map(|(i, c)| (!c.is_ascii_digit()).then_some(i))
-                        .unwrap_or(remaining.len());
+                    if let Some(len) = date_len(bytes) {
+                        return self.token(TokenKind::Date, len);
+                    }
+                    let digits = |start: usize| {
+                        remaining[start..]
+                            .find(|c: char| !c.is_ascii_digit())
+                            .map_or(remaining.len(), |i| start + i)
+                    };
+                    let mut len = digits(0);
+                    // Decimal part
+                    if remaining[len..].starts_with('.')
+                        && remaining[len + 1..].starts_with(|c: char| c.is_ascii_digit())
+                    {
+                        len = digits(len + 1);
+                    } else if let [b's' | b'm' | b'h' | b'd' | b'w', next @ ..] = &bytes[len..] {
+                        if !next.first().is_some_and(|c| c.is_ascii_alphanumeric()) {
+                            return self.token(TokenKind::Duration, len + 1);
+                        }
+                    }
                     self.token(TokenKind::Nb, len)
                 }
                 _ => {
                     // Search end of possible slice
                     let len = chars
-                        .find_map(|(i, c)| (!c.is_alphanumeric()).then_some(i))
+                        .find_map(|(i, c)| (!c.is_alphanumeric() && c != '_').then_some(i))
                         .unwrap_or(remaining.len());
+                    // Operator keyword followed by the case-insensitive modifier, only where an
+                    // operator is expected to keep names like lei or ini
+                    let word = &remaining[..len];
+                    let len = match word.strip_suffix('i') {
+                        Some(
+                            op @ ("eq" | "ne" | "gt" | "lt" | "ge" | "le" | "contains"
+                            | "startswith" | "endswith" | "in"),
+                        ) if self.after_operand => op.len(),
+                        _ => len,
+                    };
                     let kind = match &remaining[..len] {
                         "eq" => TokenKind::Cmp(CmpOp::Eq),
                         "ne" => TokenKind::Cmp(CmpOp::Ne),
@@ -182,6 +282,10 @@ impl<'a> Lexer<'a> {
                         "all" => TokenKind::Match(MatchOp::All),
                         "any" => TokenKind::Match(MatchOp::Any),
                         "matches" => TokenKind::Matches,
+                        "contains" => TokenKind::Pred(StrOp::Contains),
+                        "startswith" => TokenKind::Pred(StrOp::StartsWith),
+                        "endswith" => TokenKind::Pred(StrOp::EndsWith),
+                        "in" => TokenKind::In,
                         "not" => TokenKind::Not,
                         str => {
                             if str.parse::<Decimal>().is_ok() {
@@ -199,3 +303,61 @@ impl<'a> Lexer<'a> {
         }
     }
 }
+
+/// Length of an ISO 8601 date at the start of bytes, with optional time and offset
+fn date_len(bytes: &[u8]) -> Option<usize> {
+    // Match bytes against a pattern where 0 is any digit
+    let pattern = |pos: usize, pattern: &[u8]| {
+        let slice = bytes.get(pos..pos + pattern.len())?;
+        slice
+            .iter()
+            .zip(pattern)
+            .all(|(c, p)| {
+                if *p == b'0' {
+                    c.is_ascii_digit()
+                } else {
+                    c == p
+                }
+            })
+            .then_some(pos + pattern.len())
+    };
+    let mut len = pattern(0, b"0000-00-00")?;
+    if let Some(time) = pattern(len, b"T00:00") {
+        len = time;
+        if let Some(secs) = pattern(len, b":00") {
+            len = secs;
+            if let Some(frac) = pattern(len, b".0") {
+                len = frac
+                    + bytes[frac..]
+                        .iter()
+                        .take_while(|c| c.is_ascii_digit())
+                        .count();
+            }
+        }
+        if let Some(utc) = pattern(len, b"Z") {
+            len = utc;
+        } else if let Some(offset) = pattern(len, b"+00:00").or_else(|| pattern(len, b"-00:00")) {
+            len = offset;
+        }
+    }
+    Some(len)
+}
+
+/// Position of the / closing a regex literal, skipping escaped characters
+pub fn regex_end(str: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in str.char_indices().skip(1) {
+        match c {
+            '/' if !escaped => return Some(i),
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Check if str starts with a whole word
+pub fn is_word(str: &str, word: &str) -> bool {
+    str.strip_prefix(word)
+        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+}
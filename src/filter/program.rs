@@ -0,0 +1,220 @@
+use aho_corasick::AhoCorasick;
+use memchr::memmem;
+
+use super::{
+    compiler::{Expr, Filter, Finder, Node},
+    lexer::{CmpOp, LogiOp, MatchOp},
+};
+
+/** The logical part of a filter is compiled to a flat sequence of instructions evaluated with
+a single boolean register, actions are still evaluated from their nodes. */
+
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    /// Evaluate an action node into the register
+    Test(u32),
+    Not,
+    /// Jump to an instruction when the register has this value
+    Jump {
+        when: bool,
+        to: u32,
+    },
+}
+
+/// Literals of which one must be in a record for it to match
+type Clause = Vec<Vec<u8>>;
+
+/// Compile the program of a parsed filter and its record prefilter
+pub fn build(filter: &mut Filter) {
+    if filter.nodes.is_empty() {
+        return;
+    }
+    let mut program = Vec::new();
+    emit(filter, filter.start, &mut program);
+    let mut clauses = required(filter, filter.start);
+    // An empty literal is in every record
+    clauses.retain(|clause| clause.iter().all(|literal| !literal.is_empty()));
+    filter.prefilter = clauses.into_iter().map(finder).collect();
+    filter.program = program;
+}
+
+fn finder(clause: Clause) -> Finder {
+    let any = (clause.len() > 1)
+        .then(|| AhoCorasick::new(&clause).ok())
+        .flatten();
+    let needles = clause
+        .iter()
+        .map(|needle| memmem::Finder::new(needle).into_owned())
+        .collect();
+    Finder { needles, any }
+}
+
+fn emit(filter: &Filter, node: u32, program: &mut Vec<Op>) {
+    match &filter.nodes[node as usize] {
+        Node::Binary { op, .. } => {
+            // Evaluate the cheapest operands first, any of them can decide the result
+            let mut operands = Vec::new();
+            flatten(filter, node, *op, &mut operands);
+            operands.sort_by_key(|operand| cost(filter, *operand));
+            let mut jumps = Vec::new();
+            for (i, operand) in operands.iter().enumerate() {
+                emit(filter, *operand, program);
+                if i + 1 < operands.len() {
+                    jumps.push(program.len());
+                    program.push(Op::Jump {
+                        when: *op == LogiOp::Or,
+                        to: 0,
+                    });
+                }
+            }
+            let end = program.len() as u32;
+            for jump in jumps {
+                if let Op::Jump { to, .. } = &mut program[jump] {
+                    *to = end;
+                }
+            }
+        }
+        Node::Not(node) => {
+            emit(filter, *node, program);
+            program.push(Op::Not);
+        }
+        _ => program.push(Op::Test(node)),
+    }
+}
+
+/// Collect operands of successive operations with the same operator
+fn flatten(filter: &Filter, node: u32, op: LogiOp, operands: &mut Vec<u32>) {
+    match &filter.nodes[node as usize] {
+        Node::Binary {
+            lhs,
+            op: node_op,
+            rhs,
+        } if *node_op == op => {
+            flatten(filter, *lhs, op, operands);
+            flatten(filter, *rhs, op, operands);
+        }
+        _ => operands.push(node),
+    }
+}
+
+/// Estimated cost of the evaluation of an expression
+fn expr_cost(filter: &Filter, expr: u32) -> u32 {
+    match &filter.exprs[expr as usize] {
+        Expr::Col(_) | Expr::Each(_) => 2,
+        Expr::Nb(_) | Expr::Str(_) | Expr::Date(..) | Expr::Dur(_) | Expr::Row | Expr::Offset => 0,
+        Expr::Neg(expr) => expr_cost(filter, *expr),
+        Expr::Arith { lhs, rhs, .. } => 1 + expr_cost(filter, *lhs) + expr_cost(filter, *rhs),
+        Expr::Call { args, .. } => 4 + args.iter().map(|arg| expr_cost(filter, *arg)).sum::<u32>(),
+        Expr::Capture { arg, .. } => 20 + expr_cost(filter, *arg),
+    }
+}
+
+/// Estimated cost of the evaluation of a node
+fn cost(filter: &Filter, node: u32) -> u32 {
+    let values = |range: &std::ops::Range<u32>| {
+        filter.values[range.start as usize..range.end as usize]
+            .iter()
+            .map(|value| 1 + expr_cost(filter, *value))
+            .sum::<u32>()
+    };
+    match &filter.nodes[node as usize] {
+        Node::Exist(lhs) => expr_cost(filter, *lhs),
+        Node::Cmp { lhs, range, .. } => expr_cost(filter, *lhs) + values(range),
+        Node::Pred {
+            lhs, range, finder, ..
+        } => match finder {
+            Some(_) => 2 + expr_cost(filter, *lhs),
+            None => expr_cost(filter, *lhs) + values(range),
+        },
+        Node::In { lhs, set, .. } => {
+            let exprs = &filter.sets[*set as usize].exprs;
+            2 + expr_cost(filter, *lhs) + exprs.iter().map(|e| expr_cost(filter, *e)).sum::<u32>()
+        }
+        Node::Match { lhs, range, .. } => expr_cost(filter, *lhs) + 20 * range.len() as u32,
+        // Open selectors are estimated at 16 columns
+        Node::Each { cols, node, .. } => cost(filter, *node) * cols.len().min(16) as u32,
+        Node::Not(node) => cost(filter, *node),
+        Node::Binary { lhs, rhs, .. } => cost(filter, *lhs) + cost(filter, *rhs),
+    }
+}
+
+/// Literals required in the content of a record for a node to match, fields are substrings of
+/// the record content
+fn required(filter: &Filter, node: u32) -> Vec<Clause> {
+    let is_field =
+        |expr: &u32| matches!(filter.exprs[*expr as usize], Expr::Col(_) | Expr::Each(_));
+    // Any literal of an any list, or each of an all list
+    let per_match = |m: &MatchOp, literals: Clause| match m {
+        MatchOp::Any => vec![literals],
+        MatchOp::All => literals.into_iter().map(|literal| vec![literal]).collect(),
+    };
+    match &filter.nodes[node as usize] {
+        Node::Pred {
+            lhs,
+            m,
+            nocase: false,
+            finder: Some(finder),
+            ..
+        } if is_field(lhs) => {
+            let needles = &filter.finders[*finder as usize].needles;
+            per_match(
+                m,
+                needles
+                    .iter()
+                    .map(|needle| needle.needle().to_vec())
+                    .collect(),
+            )
+        }
+        Node::Cmp {
+            lhs,
+            op: CmpOp::Eq,
+            m,
+            range,
+            nocase: false,
+        } if is_field(lhs) => {
+            let literals = filter.values[range.start as usize..range.end as usize]
+                .iter()
+                .map(|value| match &filter.exprs[*value as usize] {
+                    Expr::Str(str) => Some(str.clone()),
+                    _ => None,
+                })
+                .collect::<Option<Clause>>();
+            literals.map_or_else(Vec::new, |literals| per_match(m, literals))
+        }
+        Node::In {
+            lhs,
+            set,
+            nocase: false,
+        } if is_field(lhs) => {
+            let set = &filter.sets[*set as usize];
+            if set.nbs.is_empty() && set.exprs.is_empty() {
+                vec![set.strs.iter().cloned().collect()]
+            } else {
+                vec![]
+            }
+        }
+        // All of no column is true
+        Node::Each {
+            m: MatchOp::Any,
+            node,
+            ..
+        } => required(filter, *node),
+        Node::Binary { lhs, op, rhs } => {
+            let (mut lhs, rhs) = (required(filter, *lhs), required(filter, *rhs));
+            match op {
+                LogiOp::And => {
+                    lhs.extend(rhs);
+                    lhs
+                }
+                LogiOp::Or => match (lhs.into_iter().next(), rhs.into_iter().next()) {
+                    (Some(mut lhs), Some(rhs)) => {
+                        lhs.extend(rhs);
+                        vec![lhs]
+                    }
+                    _ => vec![],
+                },
+            }
+        }
+        _ => vec![],
+    }
+}
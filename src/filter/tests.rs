@@ -0,0 +1,267 @@
+use proptest::prelude::*;
+
+use super::{Config, Engine, Filter};
+use crate::reader::NestedString;
+
+const NB_COL: usize = 4;
+const FIELDS: [&str; 10] = ["", "a", "ab", "A", "b a", " 7", "7", "-3", "12", "007"];
+
+/// Filter expression with a known meaning
+#[derive(Debug, Clone)]
+enum Ast {
+    Exist(usize),
+    Cmp(usize, Op, Lit),
+    Pred(usize, Pred, String, bool),
+    Not(Box<Ast>),
+    And(Box<Ast>, Box<Ast>),
+    Or(Box<Ast>, Box<Ast>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+    Ge,
+    Le,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Pred {
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+#[derive(Debug, Clone)]
+enum Lit {
+    Nb(u8),
+    Str(String),
+}
+
+impl Op {
+    fn apply<T: Ord>(self, a: T, b: T) -> bool {
+        match self {
+            Op::Eq => a == b,
+            Op::Ne => a != b,
+            Op::Gt => a > b,
+            Op::Lt => a < b,
+            Op::Ge => a >= b,
+            Op::Le => a <= b,
+        }
+    }
+}
+
+/// Reference evaluation over parsed fields, missing fields are empty
+fn eval(ast: &Ast, record: &[String]) -> bool {
+    let field = |col: &usize| record.get(*col).map_or("", String::as_str);
+    match ast {
+        Ast::Exist(col) => !field(col).is_empty(),
+        Ast::Cmp(col, op, Lit::Nb(nb)) => match field(col).trim().parse::<i64>() {
+            Ok(value) => op.apply(value, *nb as i64),
+            Err(_) => op.apply(field(col).as_bytes(), nb.to_string().as_bytes()),
+        },
+        Ast::Cmp(col, op, Lit::Str(str)) => op.apply(field(col).as_bytes(), str.as_bytes()),
+        Ast::Pred(col, pred, needle, nocase) => {
+            let (hay, needle) = match nocase {
+                true => (field(col).to_lowercase(), needle.to_lowercase()),
+                false => (field(col).to_string(), needle.clone()),
+            };
+            match pred {
+                Pred::Contains => hay.contains(&needle),
+                Pred::StartsWith => hay.starts_with(&needle),
+                Pred::EndsWith => hay.ends_with(&needle),
+            }
+        }
+        Ast::Not(ast) => !eval(ast, record),
+        Ast::And(lhs, rhs) => eval(lhs, record) && eval(rhs, record),
+        Ast::Or(lhs, rhs) => eval(lhs, record) || eval(rhs, record),
+    }
+}
+
+/// Write an expression with the fewest parentheses the precedence allows, or with all of them
+fn render(ast: &Ast, min_prec: u8, words: bool, grouped: bool) -> String {
+    let (prec, str) = match ast {
+        Ast::Exist(col) => (2, col.to_string()),
+        Ast::Cmp(col, op, lit) => {
+            let op = match op {
+                Op::Eq => "==",
+                Op::Ne => "!=",
+                Op::Gt => ">",
+                Op::Lt => "<",
+                Op::Ge => ">=",
+                Op::Le => "<=",
+            };
+            let lit = match lit {
+                Lit::Nb(nb) => nb.to_string(),
+                Lit::Str(str) => format!("\"{str}\""),
+            };
+            (2, format!("{col} {op} {lit}"))
+        }
+        Ast::Pred(col, pred, needle, nocase) => {
+            let pred = match pred {
+                Pred::Contains => "contains",
+                Pred::StartsWith => "startswith",
+                Pred::EndsWith => "endswith",
+            };
+            let nocase = if *nocase { "i" } else { "" };
+            (2, format!("{col} {pred}{nocase} \"{needle}\""))
+        }
+        Ast::Not(ast) => {
+            let not = if words { "not " } else { "!" };
+            (2, format!("{not}{}", render(ast, 2, words, grouped)))
+        }
+        Ast::And(lhs, rhs) => {
+            let op = if words { "and" } else { "&&" };
+            let (lhs, rhs) = (
+                render(lhs, 1, words, grouped),
+                render(rhs, 2, words, grouped),
+            );
+            (1, format!("{lhs} {op} {rhs}"))
+        }
+        Ast::Or(lhs, rhs) => {
+            let op = if words { "or" } else { "||" };
+            let (lhs, rhs) = (
+                render(lhs, 0, words, grouped),
+                render(rhs, 1, words, grouped),
+            );
+            (0, format!("{lhs} {op} {rhs}"))
+        }
+    };
+    if prec < min_prec || (grouped && prec < 2) {
+        format!("({str})")
+    } else {
+        str
+    }
+}
+
+fn ast() -> impl Strategy<Value = Ast> {
+    let op = prop_oneof![
+        Just(Op::Eq),
+        Just(Op::Ne),
+        Just(Op::Gt),
+        Just(Op::Lt),
+        Just(Op::Ge),
+        Just(Op::Le),
+    ];
+    let pred = prop_oneof![
+        Just(Pred::Contains),
+        Just(Pred::StartsWith),
+        Just(Pred::EndsWith),
+    ];
+    let lit = prop_oneof![
+        (0..15u8).prop_map(Lit::Nb),
+        "[aAb ]{0,3}".prop_map(Lit::Str),
+    ];
+    let leaf = prop_oneof![
+        (0..NB_COL).prop_map(Ast::Exist),
+        (0..NB_COL, op, lit).prop_map(|(col, op, lit)| Ast::Cmp(col, op, lit)),
+        (0..NB_COL, pred, "[aAb ]{0,2}", any::<bool>())
+            .prop_map(|(col, pred, needle, nocase)| Ast::Pred(col, pred, needle, nocase)),
+    ];
+    leaf.prop_recursive(5, 32, 2, |inner| {
+        prop_oneof![
+            inner.clone().prop_map(|ast| Ast::Not(Box::new(ast))),
+            (inner.clone(), inner.clone())
+                .prop_map(|(lhs, rhs)| Ast::And(Box::new(lhs), Box::new(rhs))),
+            (inner.clone(), inner).prop_map(|(lhs, rhs)| Ast::Or(Box::new(lhs), Box::new(rhs))),
+        ]
+    })
+}
+
+fn record() -> impl Strategy<Value = Vec<&'static str>> {
+    prop::collection::vec(prop::sample::select(&FIELDS[..]), 1..=NB_COL + 1)
+}
+
+fn parse(line: &str) -> NestedString {
+    let mut record = NestedString::new();
+    let mut rdr = csv_core::Reader::new();
+    record.read_record(&mut line.as_bytes(), &mut rdr).unwrap();
+    record
+}
+
+proptest! {
+    #[test]
+    fn check_matches_reference(ast in ast(), records in prop::collection::vec(record(), 1..8)) {
+        let headers = parse("a,b,c,d\n");
+        let config = Config::default();
+        for (words, grouped) in [(false, false), (true, false), (false, true)] {
+            let source = render(&ast, 0, words, grouped);
+            let filter = match Filter::new(&source, &headers, NB_COL, &config) {
+                Ok(filter) => filter,
+                Err((span, msg)) => panic!("{source:?} at {span:?}: {msg}"),
+            };
+            let engine = Engine::new(&filter);
+            for fields in &records {
+                let record = parse(&format!("{}\n", fields.join(",")));
+                let parsed: Vec<String> = record.iter().map(|field| field.to_string()).collect();
+                prop_assert_eq!(
+                    engine.check(&record, 0, 0),
+                    eval(&ast, &parsed),
+                    "{} on {:?}",
+                    source,
+                    fields
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn readme_examples_parse() {
+    let readme = include_str!("../../README.md");
+    let syntax = &readme[readme.find("## Filter syntax").unwrap()..];
+    // Named columns used by the examples, then enough to reference column 43
+    let mut line = String::from(
+        "name,email,price,unit price,2019,sku,shipped_at,ordered_at,created,due,delivered,\
+        ordered,version,url,id,status,message,total,qty,cost,phone",
+    );
+    for i in 21..50 {
+        line.push_str(&format!(",c{i}"));
+    }
+    let headers = parse(&format!("{line}\n"));
+    let config = Config::default();
+    let blocks = syntax.split("```").skip(1).step_by(2);
+    for source in blocks.flat_map(str::lines).filter(|line| !line.is_empty()) {
+        if let Err((span, msg)) = Filter::new(source, &headers, headers.len(), &config) {
+            panic!("{source:?} at {span:?}: {msg}");
+        }
+    }
+}
+
+#[test]
+fn grouped_operands_parse() {
+    let headers = parse("name,id\n");
+    let config = Config::default();
+    for source in [
+        "(lower(name)) contains \"x\"",
+        "(lower(name)) containsi x",
+        "(name) startswith any {a, b}",
+        "(id) in {1, 2}",
+        "(id + 1) not in {1, 2}",
+        "(id) ==i a",
+        "(id) ~ a",
+    ] {
+        if let Err((span, msg)) = Filter::new(source, &headers, 2, &config) {
+            panic!("{source:?} at {span:?}: {msg}");
+        }
+    }
+}
+
+#[test]
+fn date_literals() {
+    let headers = parse("d\n");
+    let config = Config::default();
+    let check = |source: &str, field: &str| {
+        let filter = Filter::new(source, &headers, 1, &config).unwrap();
+        Engine::new(&filter).check(&parse(&format!("{field}\n")), 0, 0)
+    };
+    for literal in ["2023-01-01T12:00Z", "2023-01-01T12:00+00:00"] {
+        assert!(check(&format!("d == {literal}"), "2023-01-01T12:00:00Z"));
+        assert!(check(&format!("d < {literal}"), "2023-01-01T13:00+02:00"));
+    }
+    // Fields that are not dates are compared with the literal as written
+    assert!(check("d < 2023-01-01T12:00+02:00", "2023-01-01T11x"));
+    assert!(check("d > 2023-01-01T12:00Z", "2023-01-01T12:00Zx"));
+}
@@ -35,6 +35,54 @@ impl Ty {
     }
 }
 
+/// Kind of the content of a column over sampled records
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum ColType {
+    Empty,
+    Nb,
+    Bool,
+    Str,
+}
+
+impl ColType {
+    /// Merge the kind of another content, mixed kinds are text
+    pub fn add(self, s: &BStr) -> Self {
+        let s = BStr::new(s.trim());
+        if s.is_empty() {
+            return self;
+        }
+        let ty = match Ty::guess(s) {
+            Ty::Bool => ColType::Bool,
+            Ty::Nb { .. } => ColType::Nb,
+            Ty::Str => ColType::Str,
+        };
+        match self {
+            ColType::Empty => ty,
+            _ if self == ty => ty,
+            _ => ColType::Str,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColType::Empty => "empty",
+            ColType::Nb => "number",
+            ColType::Bool => "bool",
+            ColType::Str => "text",
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        [ColType::Empty, ColType::Nb, ColType::Bool, ColType::Str]
+            .get(byte as usize)
+            .copied()
+    }
+}
+
 pub struct ColStat {
     header_len: usize,
     align_decimal: bool,
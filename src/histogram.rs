@@ -16,11 +16,17 @@ use crate::{
     filter::{Engine, Filter},
     fmt::{ColStat, Fmt, Ty},
     nb_print_len,
-    reader::{CsvReader, NestedString},
+    reader::{located, CsvReader, NestedString},
     source::Source,
+    spool::Spool,
     style, Nav,
 };
 
+/// Number of records read to suggest values
+const TOP_LEN: usize = 10_000;
+/// Number of suggested values
+const TOP_VALUES: usize = 20;
+
 struct Histogram {
     /// Map value to their count index
     values: IndexMap<BString, usize>,
@@ -76,12 +82,104 @@ impl Histogram {
     }
 }
 
+/// Most frequent non-empty contents of a column in the first records of a source
+struct TopState {
+    values: Mutex<Vec<BString>>,
+    error: Mutex<Option<String>>,
+}
+
+/// Frequent values of a column sampled in the background from the start of the file
+pub struct TopValues {
+    off: usize,
+    state: Arc<TopState>,
+}
+
+impl TopValues {
+    pub fn sample(source: &Source, off: usize) -> io::Result<Self> {
+        // Only read spooled content to never wait for streamed data
+        let (rdr, _) = source.written_reader()?;
+        let partial = source.spool().is_some_and(|spool| !spool.is_done());
+        let state = Arc::new(TopState {
+            values: Mutex::new(Vec::new()),
+            error: Mutex::new(None),
+        });
+
+        {
+            let state = state.clone();
+            thread::spawn(move || {
+                if let Err(e) = Self::bg_sample(rdr, off, partial, &state) {
+                    *state.error.lock() = Some(format!("Completion failed: {e}"));
+                }
+            });
+        }
+
+        Ok(Self { off, state })
+    }
+
+    fn bg_sample(
+        mut rdr: CsvReader,
+        off: usize,
+        partial: bool,
+        state: &Arc<TopState>,
+    ) -> io::Result<()> {
+        let mut histogram = Histogram::new();
+        let mut record = NestedString::new();
+        let mut pos = rdr.pos()?;
+        for _ in 0..TOP_LEN {
+            // The last record of a partial stream may be cut
+            let amount = if partial {
+                rdr.complete_record(&mut record)
+            } else {
+                rdr.record(&mut record)
+            }
+            .map_err(|e| located(e, pos))?;
+            if amount == 0 {
+                break;
+            } else if Arc::strong_count(state) == 1 {
+                return Ok(());
+            }
+            pos += amount as u64;
+            let value = BStr::new(record.get(off).unwrap_or_default().trim());
+            if !value.is_empty() {
+                histogram.register(value);
+            }
+        }
+        *state.values.lock() = histogram
+            .items()
+            .take(TOP_VALUES)
+            .map(|(value, _)| value.into())
+            .collect();
+        Ok(())
+    }
+
+    /// Offset of the sampled column
+    pub fn col(&self) -> usize {
+        self.off
+    }
+
+    /// Sampled values, none while sampling
+    pub fn values(&self) -> Option<Vec<BString>> {
+        (!self.is_loading()).then(|| self.state.values.lock().clone())
+    }
+
+    // Check if the sampling is working in the background
+    pub fn is_loading(&self) -> bool {
+        Arc::strong_count(&self.state) > 1
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.state.error.lock().clone()
+    }
+}
+
 struct State {
     histogram: Mutex<Histogram>,
     file_len: u64,
+    spool: Option<Spool>,
     nb_read: AtomicU64,
     nb_item: AtomicU64,
-    nb_row: AtomicUsize, // TODO store indexer error
+    nb_row: AtomicUsize,
+    error: Mutex<Option<String>>,
 }
 pub struct Histographer {
     name: String,
@@ -95,15 +193,21 @@ impl Histographer {
         let name = headers.get(off).unwrap_or_default().to_string();
         let state = Arc::new(State {
             file_len: rdr.len()?,
+            spool: source.spool(),
             nb_read: AtomicU64::new(rdr.pos()?),
             nb_item: AtomicU64::new(0),
             nb_row: AtomicUsize::new(0),
             histogram: Mutex::new(Histogram::new()),
+            error: Mutex::new(None),
         });
 
         {
             let state = state.clone();
-            thread::spawn(move || Self::bg_analyze(rdr, off, filter, state));
+            thread::spawn(move || {
+                if let Err(e) = Self::bg_analyze(rdr, off, filter, &state) {
+                    *state.error.lock() = Some(format!("Analysis failed: {e}"));
+                }
+            });
         }
 
         Ok(Self {
@@ -117,20 +221,25 @@ impl Histographer {
         mut rdr: CsvReader,
         idx: usize,
         filter: Filter,
-        state: Arc<State>,
+        state: &Arc<State>,
     ) -> io::Result<()> {
         let engine = Engine::new(&filter);
         let mut record = NestedString::new();
+        let mut pos = rdr.pos()?;
+        let mut count = 0;
         loop {
-            let amount = rdr.record(&mut record)?;
+            let amount = rdr.record(&mut record).map_err(|e| located(e, pos))?;
             if amount == 0 {
                 break;
-            } else if Arc::strong_count(&state) == 1 {
+            } else if Arc::strong_count(state) == 1 {
                 return Ok(());
             }
             state.nb_read.fetch_add(amount as u64, Relaxed);
 
-            if engine.check(&record) {
+            let is_match = engine.check(&record, count, pos);
+            pos += amount as u64;
+            count += 1;
+            if is_match {
                 let col = BStr::new(record.get(idx).unwrap_or_default().trim());
                 let nb_row = state.histogram.lock().register(col);
                 state.nb_row.store(nb_row, Relaxed);
@@ -145,8 +254,16 @@ impl Histographer {
         Arc::strong_count(&self.state) > 1
     }
 
+    pub fn error(&self) -> Option<String> {
+        self.state.error.lock().clone()
+    }
+
     pub fn progress(&self) -> u8 {
-        (self.state.nb_read.load(Relaxed) * 100 / self.state.file_len.max(1)) as u8
+        let nb_read = self.state.nb_read.load(Relaxed);
+        match &self.state.spool {
+            Some(spool) => spool.progress(nb_read),
+            None => (nb_read * 100 / self.state.file_len.max(1)) as u8,
+        }
     }
 
     pub fn up(&mut self) {
@@ -1,27 +1,78 @@
 use std::{
+    collections::BTreeMap,
     io::{self},
+    ops::Range,
     sync::{
         atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed},
-        Arc,
+        mpsc, Arc,
     },
     thread,
+    time::Duration,
 };
 
 use parking_lot::Mutex;
 
 use crate::{
+    cache::IndexCache,
     filter::{Engine, Filter},
-    reader::{CsvReader, NestedString},
+    fmt::ColType,
+    reader::{located, CsvReader, NestedString},
+    row_index::RowIndex,
     source::Source,
+    spool::Spool,
 };
 
+pub const FOLLOW_POOL: Duration = Duration::from_millis(250);
+const CHUNK_LEN: u64 = 16 * 1024 * 1024;
+const PARALLEL_MIN_LEN: u64 = 4 * CHUNK_LEN;
+/// Number of records sampled for column kinds
+const TYPE_SAMPLE: u64 = 1000;
+
+/// Records indexed from a part of the file
+struct Chunk {
+    /// Position of the first record
+    start: u64,
+    /// Started after the first line feed of its range
+    synced: bool,
+    /// Position after the last record
+    end: u64,
+    /// Matching records numbered from the chunk start
+    rows: Vec<(u64, u64)>,
+    count: u64,
+    max_col: usize,
+    /// First record wider than headers numbered from the chunk start
+    wide: Option<(u64, u64, usize)>,
+    /// Column kinds of the first records
+    types: Vec<ColType>,
+}
+
+impl Chunk {
+    /// Check if the chunk starts where the previous one ended. A CRLF record ends before its line
+    /// feed, which csv_core skips as an empty line, while a synced chunk starts after it.
+    fn follows(&self, end: u64) -> bool {
+        self.start == end || (self.synced && self.start == end + 1)
+    }
+}
+
 struct State {
-    index: Mutex<Vec<(u32, u64)>>,
+    index: Mutex<RowIndex>,
     filter: Filter,
-    file_len: u64,
+    follow: bool,
+    /// Cache of the unfiltered index
+    cache: Option<IndexCache>,
+    file_len: AtomicU64,
+    spool: Option<Spool>,
     nb_col: AtomicUsize,
     nb_read: AtomicU64,
-    // TODO store indexer error
+    /// Number of headers, zero without headers
+    nb_header: usize,
+    error: Mutex<Option<String>>,
+    /// First malformed record diagnostic
+    warning: Mutex<Option<String>>,
+    /// Column kinds of the first records
+    types: Mutex<Vec<ColType>>,
+    /// Position after the content stored in cache
+    cached_end: AtomicU64,
 }
 
 pub struct Indexer {
@@ -31,64 +82,434 @@ pub struct Indexer {
 impl Indexer {
     pub fn index(source: &Source, filter: Filter) -> io::Result<(NestedString, Self)> {
         let (mut rdr, headers) = source.reader()?;
+        let (file_len, pos) = (rdr.len()?, rdr.pos()?);
+        // Index big files in parallel when their content is complete and rows are independent
+        let nb_worker = thread::available_parallelism().map_or(1, |n| n.get());
+        let readers = if source.spool().is_none()
+            && !source.is_following()
+            && !filter.use_row()
+            && nb_worker > 1
+            && file_len - pos >= PARALLEL_MIN_LEN
+        {
+            (0..nb_worker)
+                .map(|_| source.reader().map(|(rdr, _)| rdr))
+                .collect::<io::Result<_>>()?
+        } else {
+            vec![]
+        };
         let state = Arc::new(State {
-            index: Mutex::new(Vec::with_capacity(1000)),
+            index: Mutex::new(RowIndex::new()),
+            cache: filter.nodes.is_empty().then(|| source.cache()).flatten(),
             filter,
-            file_len: rdr.len()?,
+            follow: source.is_following(),
+            file_len: AtomicU64::new(file_len),
+            spool: source.spool(),
             nb_col: AtomicUsize::new(0),
-            nb_read: AtomicU64::new(rdr.pos()?),
+            nb_read: AtomicU64::new(pos),
+            nb_header: headers.len(),
+            error: Mutex::new(None),
+            warning: Mutex::new(None),
+            types: Mutex::new(Vec::new()),
+            cached_end: AtomicU64::new(0),
         });
 
         {
             let state = state.clone();
-            thread::spawn(|| Self::bg_index(rdr, state));
+            thread::spawn(move || {
+                if let Err(e) = Self::bg_index(rdr, readers, &state) {
+                    *state.error.lock() = Some(format!("Indexing failed: {e}"));
+                }
+            });
+        }
+
+        Ok((headers, Self { state }))
+    }
+
+    /// Index the rows of this completed index matching a narrower filter, without reading the
+    /// other rows. Index the whole file when this index may still grow.
+    pub fn refine(&self, source: &Source, filter: Filter) -> io::Result<(NestedString, Self)> {
+        if self.is_loading() || source.is_following() {
+            return Self::index(source, filter);
+        }
+        let (mut rdr, headers) = source.reader()?;
+        let state = Arc::new(State {
+            index: Mutex::new(RowIndex::new()),
+            cache: None,
+            filter,
+            follow: false,
+            file_len: AtomicU64::new(self.state.file_len.load(Relaxed)),
+            spool: source.spool(),
+            nb_col: AtomicUsize::new(self.nb_col()),
+            nb_read: AtomicU64::new(rdr.pos()?),
+            nb_header: headers.len(),
+            error: Mutex::new(None),
+            warning: Mutex::new(None),
+            types: Mutex::new(self.state.types.lock().clone()),
+            cached_end: AtomicU64::new(0),
+        });
+
+        {
+            let (state, rows) = (state.clone(), self.state.index.lock().clone());
+            let end = self.state.nb_read.load(Relaxed);
+            thread::spawn(move || {
+                if let Err(e) = Self::bg_refine(rdr, &rows, end, &state) {
+                    *state.error.lock() = Some(format!("Indexing failed: {e}"));
+                }
+            });
         }
 
         Ok((headers, Self { state }))
     }
 
-    fn bg_index(mut rdr: CsvReader, state: Arc<State>) -> io::Result<()> {
+    fn bg_refine(
+        mut rdr: CsvReader,
+        rows: &RowIndex,
+        end: u64,
+        state: &Arc<State>,
+    ) -> io::Result<()> {
+        let engine = Engine::new(&state.filter);
+        let mut record = NestedString::new();
+        for (i, (row, pos)) in rows.iter().enumerate() {
+            rdr.record_at(&mut record, pos)
+                .map_err(|e| located(e, pos))?;
+            if engine.check(&record, row, pos) {
+                state.index.lock().push(row, pos);
+            }
+            if i % 1000 == 0 {
+                // If arc is unique this task is canceled
+                if Arc::strong_count(state) == 1 {
+                    return Ok(());
+                }
+                state.nb_read.store(pos, Relaxed);
+            }
+        }
+        state.nb_read.store(end, Relaxed);
+        Ok(())
+    }
+
+    fn bg_index(mut rdr: CsvReader, readers: Vec<CsvReader>, state: &Arc<State>) -> io::Result<()> {
         let engine = Engine::new(&state.filter);
         let mut record = NestedString::new();
-        let mut buff_pos = Vec::with_capacity(100);
         let mut pos = state.nb_read.load(Relaxed);
         let mut max_col = state.nb_col.load(Relaxed);
 
         let mut count = 0;
+        let mut types = Vec::new();
+        // Resume from cached index
+        if let Some(cached) = state.cache.as_ref().and_then(|c| c.load().ok().flatten()) {
+            count = cached.index.len() as u64;
+            *state.index.lock() = cached.index;
+            pos = cached.end;
+            max_col = max_col.max(cached.nb_col);
+            types = cached.types;
+            rdr.seek(pos)?;
+            *state.types.lock() = types.clone();
+            state.cached_end.store(pos, Relaxed);
+            state.nb_col.store(max_col, Relaxed);
+            state.nb_read.store(pos, Relaxed);
+        } else if !readers.is_empty() {
+            if let Some((count, pos, max_col)) = Self::index_chunks(&mut rdr, readers, state, pos)?
+            {
+                state.nb_col.store(max_col, Relaxed);
+                state.nb_read.store(pos, Relaxed);
+                if count > 0 {
+                    // Write a copy to not block readers
+                    let index = state.index.lock().clone();
+                    Self::store_cache(state, &index, max_col, pos);
+                }
+            }
+            return Ok(());
+        }
+        let mut is_wide = false;
+        let mut is_synced = false;
+
         loop {
-            let amount = rdr.record(&mut record)?;
+            let amount = if state.follow {
+                rdr.complete_record(&mut record)
+            } else {
+                rdr.record(&mut record)
+            }
+            .map_err(|e| located(e, pos))?;
             if amount == 0 {
-                break;
-            } else if engine.check(&record) {
-                state.index.lock().push((count, pos));
+                if count <= TYPE_SAMPLE {
+                    *state.types.lock() = types.clone();
+                }
+                // Followed content is stored again when canceled
+                if !is_synced && count > 0 {
+                    is_synced = true;
+                    let index = state.index.lock().clone();
+                    Self::store_cache(state, &index, max_col, pos);
+                }
+                if !state.follow {
+                    break;
+                }
+                // Wait for appended data
+                if Arc::strong_count(state) == 1 {
+                    Self::store_canceled(state, max_col, pos);
+                    return Ok(());
+                }
+                state.nb_col.store(max_col, Relaxed);
+                state.nb_read.store(pos, Relaxed);
+                state.file_len.store(rdr.len()?, Relaxed);
+                thread::sleep(FOLLOW_POOL);
+                continue;
+            } else if engine.check(&record, count, pos) {
+                state.index.lock().push(count, pos);
+            }
+
+            if !is_wide && state.nb_header > 0 && record.len() > state.nb_header {
+                is_wide = true;
+                Self::warn_wide(state, count, pos, record.len());
+            }
+            if count < TYPE_SAMPLE {
+                sample_types(&mut types, &record);
             }
 
             pos += amount as u64;
             count += 1;
             max_col = max_col.max(record.len());
 
-            // Throttle locking
-            if count % 1000 == 0 {
+            // Throttle locking, but publish progress before waiting for streamed data
+            if count % 1000 == 0 || rdr.is_drained() {
                 // If arc is unique this task is canceled
-                if Arc::strong_count(&state) == 1 {
+                if Arc::strong_count(state) == 1 {
+                    Self::store_canceled(state, max_col, pos);
                     return Ok(());
                 }
-                if !buff_pos.is_empty() {
-                    state.index.lock().append(&mut buff_pos);
+                if count <= TYPE_SAMPLE {
+                    *state.types.lock() = types.clone();
                 }
                 state.nb_col.store(max_col, Relaxed);
                 state.nb_read.store(pos, Relaxed);
             }
         }
 
-        if !buff_pos.is_empty() {
-            state.index.lock().append(&mut buff_pos);
-        }
         state.nb_col.store(max_col, Relaxed);
         state.nb_read.store(pos, Relaxed);
         Ok(())
     }
 
+    /// Index content from start by chunks in parallel, merging them in order as soon as possible.
+    /// Return the number of record, the end position and the max number of columns, or None if
+    /// the task is canceled.
+    fn index_chunks(
+        rdr: &mut CsvReader,
+        readers: Vec<CsvReader>,
+        state: &Arc<State>,
+        start: u64,
+    ) -> io::Result<Option<(u64, u64, usize)>> {
+        let file_len = state.file_len.load(Relaxed);
+        let nb_chunk = (file_len - start).div_ceil(CHUNK_LEN);
+        let next = AtomicU64::new(0);
+        let (sender, receiver) = mpsc::channel();
+
+        thread::scope(|s| {
+            for mut rdr in readers {
+                let (sender, next) = (sender.clone(), &next);
+                s.spawn(move || {
+                    let engine = Engine::new(&state.filter);
+                    let mut record = NestedString::new();
+                    loop {
+                        let i = next.fetch_add(1, Relaxed);
+                        if i >= nb_chunk {
+                            break;
+                        }
+                        let chunk_start = start + i * CHUNK_LEN;
+                        let chunk = Self::index_chunk(
+                            &mut rdr,
+                            &engine,
+                            &mut record,
+                            state,
+                            chunk_start..chunk_start + CHUNK_LEN,
+                            i > 0,
+                            true,
+                        );
+                        let stop = !matches!(chunk, Ok(Some(_)));
+                        if sender.send((i, chunk)).is_err() || stop {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(sender);
+
+            // Merge chunks in order
+            let engine = Engine::new(&state.filter);
+            let mut record = NestedString::new();
+            let mut pending = BTreeMap::new();
+            let (mut i, mut count, mut pos, mut max_col) = (0, 0, start, 0);
+            for (idx, chunk) in receiver {
+                match chunk? {
+                    Some(chunk) => pending.insert(idx, chunk),
+                    None => return Ok(None),
+                };
+                while let Some(mut chunk) = pending.remove(&i) {
+                    if !chunk.follows(pos) {
+                        // Chunk started on a line break inside a quoted field, index it again
+                        // from the previous chunk end and replace the progress of the first pass
+                        let end = start + (i + 1) * CHUNK_LEN;
+                        let counted = chunk.end - chunk.start;
+                        let range = pos..end;
+                        match Self::index_chunk(
+                            rdr,
+                            &engine,
+                            &mut record,
+                            state,
+                            range,
+                            false,
+                            false,
+                        )? {
+                            Some(reindexed) => chunk = reindexed,
+                            None => return Ok(None),
+                        }
+                        let len = chunk.end - chunk.start;
+                        if len > counted {
+                            state.nb_read.fetch_add(len - counted, Relaxed);
+                        } else {
+                            state.nb_read.fetch_sub(counted - len, Relaxed);
+                        }
+                    } else {
+                        // Count the skipped line feed
+                        state.nb_read.fetch_add(chunk.start - pos, Relaxed);
+                    }
+                    let mut index = state.index.lock();
+                    for (row, off) in &chunk.rows {
+                        index.push(count + row, *off);
+                    }
+                    drop(index);
+                    if let Some((row, off, len)) = chunk.wide {
+                        Self::warn_wide(state, count + row, off, len);
+                    }
+                    if i == 0 {
+                        *state.types.lock() = std::mem::take(&mut chunk.types);
+                    }
+                    count += chunk.count;
+                    pos = chunk.end;
+                    max_col = max_col.max(chunk.max_col);
+                    state.nb_col.store(max_col, Relaxed);
+                    i += 1;
+                }
+            }
+            Ok(Some((count, pos, max_col)))
+        })
+    }
+
+    /// Index records starting in range, from its start or from the first line start after it.
+    /// Syncing on a line break is not quote-aware, a chunk starting inside a quoted field has
+    /// another start than the previous chunk end and is indexed again when merged.
+    fn index_chunk(
+        rdr: &mut CsvReader,
+        engine: &Engine,
+        record: &mut NestedString,
+        state: &Arc<State>,
+        range: Range<u64>,
+        sync: bool,
+        progress: bool,
+    ) -> io::Result<Option<Chunk>> {
+        let mut pos = if sync {
+            rdr.seek(range.start - 1)?;
+            range.start - 1 + rdr.skip_line()?
+        } else {
+            rdr.seek(range.start)?;
+            range.start
+        };
+        let mut chunk = Chunk {
+            start: pos,
+            synced: sync,
+            end: pos,
+            rows: Vec::new(),
+            count: 0,
+            max_col: 0,
+            wide: None,
+            types: Vec::new(),
+        };
+        let (mut count, mut nb_read) = (0, 0);
+        while pos < range.end {
+            let amount = rdr.record(record).map_err(|e| located(e, pos))?;
+            if amount == 0 {
+                break;
+            } else if engine.check(record, count, pos) {
+                chunk.rows.push((count, pos));
+            }
+            if chunk.wide.is_none() && state.nb_header > 0 && record.len() > state.nb_header {
+                chunk.wide = Some((count, pos, record.len()));
+            }
+            if count < TYPE_SAMPLE {
+                sample_types(&mut chunk.types, record);
+            }
+            pos += amount as u64;
+            nb_read += amount as u64;
+            count += 1;
+            chunk.max_col = chunk.max_col.max(record.len());
+
+            if count % 1000 == 0 {
+                // If arc is unique this task is canceled
+                if Arc::strong_count(state) == 1 {
+                    return Ok(None);
+                }
+                if progress {
+                    state.nb_read.fetch_add(nb_read, Relaxed);
+                }
+                nb_read = 0;
+            }
+        }
+        if progress {
+            state.nb_read.fetch_add(nb_read, Relaxed);
+        }
+        chunk.count = count;
+        chunk.end = pos;
+        Ok(Some(chunk))
+    }
+
+    /// Report the first record with more fields than headers
+    fn warn_wide(state: &State, row: u64, pos: u64, nb_field: usize) {
+        state.warning.lock().get_or_insert_with(|| {
+            format!(
+                "Row {} at byte {pos} has {nb_field} fields for {} headers",
+                row + 1,
+                state.nb_header
+            )
+        });
+    }
+
+    /// Store an index ending at this position in cache, if not already stored
+    fn store_cache(state: &State, index: &RowIndex, nb_col: usize, end: u64) {
+        if let Some(cache) = &state.cache {
+            if state.cached_end.swap(end, Relaxed) != end {
+                let types = state.types.lock().clone();
+                cache.store(index, nb_col, end, &types).ok();
+            }
+        }
+    }
+
+    /// Store the rows indexed until a record boundary once the indexer is dropped, no reader
+    /// is left to block
+    fn store_canceled(state: &State, nb_col: usize, end: u64) {
+        if state.cache.is_some() {
+            let index = state.index.lock();
+            if index.len() > 0 {
+                Self::store_cache(state, &index, nb_col, end);
+            }
+        }
+    }
+
+    /// Drop the indexer, waiting for its background task to store the index in cache
+    pub fn close(self) {
+        if self.state.cache.is_none() {
+            return;
+        }
+        let state = Arc::downgrade(&self.state);
+        drop(self);
+        while state.strong_count() > 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Get the kind of a column sampled from the first records
+    pub fn col_type(&self, off: usize) -> Option<ColType> {
+        self.state.types.lock().get(off).copied()
+    }
+
     // Check if the indexer is working in the background
     pub fn is_loading(&self) -> bool {
         Arc::strong_count(&self.state) > 1
@@ -100,9 +521,22 @@ impl Indexer {
     }
 
     /// Get offsets of given rows
-    pub fn get_offsets(&self, rows: impl Iterator<Item = usize>) -> Vec<(u32, u64)> {
+    pub fn get_offsets(&self, rows: Range<usize>) -> Vec<(u64, u64)> {
         let locked = self.state.index.lock();
-        rows.map_while(|i| locked.get(i).copied()).collect()
+        locked.iter_from(rows.start).take(rows.len()).collect()
+    }
+
+    /// Find the position of a record in the index, or where it would be
+    pub fn find_row(&self, row: u64) -> Result<usize, usize> {
+        self.state.index.lock().find(row)
+    }
+
+    /// Get the indexing or input error, or take the malformed record diagnostic
+    pub fn error(&self) -> Option<String> {
+        let error = self.state.error.lock().clone();
+        error
+            .or_else(|| self.state.spool.as_ref().and_then(Spool::error))
+            .or_else(|| self.state.warning.lock().take())
     }
 
     pub fn filter(&self) -> &Filter {
@@ -118,6 +552,103 @@ impl Indexer {
     }
 
     pub fn progress(&self) -> u8 {
-        (self.state.nb_read.load(Relaxed) * 100 / self.state.file_len.max(1)) as u8
+        let nb_read = self.state.nb_read.load(Relaxed);
+        match &self.state.spool {
+            Some(spool) => spool.progress(nb_read),
+            None => (nb_read * 100 / self.state.file_len.load(Relaxed).max(1)).min(100) as u8,
+        }
+    }
+}
+
+/// Extend column kinds with the fields of a record
+fn sample_types(types: &mut Vec<ColType>, record: &NestedString) {
+    if types.len() < record.len() {
+        types.resize(record.len(), ColType::Empty);
+    }
+    for (ty, field) in types.iter_mut().zip(record.iter()) {
+        *ty = ty.add(field);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Write};
+
+    use super::*;
+    use crate::{reader::Dialect, spool::Input};
+
+    #[test]
+    fn crlf_chunks_follow() {
+        let mut tmp = tempfile::tempfile().unwrap();
+        for i in 0..100 {
+            write!(tmp, "{i},\"line\r\nbreak\",{}\r\n", "x".repeat(i % 7)).unwrap();
+        }
+        let dialect = Dialect {
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            double_quote: true,
+            comment: None,
+        };
+        let mut rdr = CsvReader::new(BufReader::new(Input::file(tmp)), &dialect);
+        let state = Arc::new(State {
+            index: Mutex::new(RowIndex::new()),
+            cache: None,
+            filter: Filter::empty(),
+            follow: false,
+            file_len: AtomicU64::new(rdr.len().unwrap()),
+            spool: None,
+            nb_col: AtomicUsize::new(0),
+            nb_read: AtomicU64::new(0),
+            nb_header: 0,
+            error: Mutex::new(None),
+            warning: Mutex::new(None),
+            types: Mutex::new(Vec::new()),
+            cached_end: AtomicU64::new(0),
+        });
+        let engine = Engine::new(&state.filter);
+        let mut record = NestedString::new();
+        let file_len = state.file_len.load(Relaxed);
+        let whole = Indexer::index_chunk(
+            &mut rdr,
+            &engine,
+            &mut record,
+            &state,
+            0..file_len,
+            false,
+            false,
+        )
+        .unwrap()
+        .unwrap();
+        let (mut end, mut count, mut nb_accepted) = (0, 0, 0);
+        for start in (0..file_len).step_by(50) {
+            let range = start..start + 50;
+            let chunk = Indexer::index_chunk(
+                &mut rdr,
+                &engine,
+                &mut record,
+                &state,
+                range.clone(),
+                start > 0,
+                false,
+            )
+            .unwrap()
+            .unwrap();
+            let chunk = if chunk.follows(end) {
+                nb_accepted += 1;
+                chunk
+            } else {
+                // Synced inside the quoted field
+                let range = end..range.end;
+                Indexer::index_chunk(&mut rdr, &engine, &mut record, &state, range, false, false)
+                    .unwrap()
+                    .unwrap()
+            };
+            count += chunk.count;
+            end = chunk.end;
+        }
+        assert_eq!((count, end), (whole.count, whole.end));
+        // Chunks syncing on a record end are kept
+        assert!(nb_accepted > file_len / 50 / 2, "{nb_accepted}");
     }
 }
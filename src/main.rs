@@ -2,19 +2,21 @@ use std::{
     io::{self},
     ops::Add,
     path::PathBuf,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use bstr::{BStr, ByteSlice};
+use cache::IndexCache;
 use clap::Parser;
 use cols::{Cols, ColsCmd, SizeCmd};
-use filter::Filter;
+use filter::{Config, DateConfig, Filter, Tz};
 use fmt::{ColStat, Fmt, Ty};
-use histogram::Histographer;
+use histogram::{Histographer, TopValues};
 use index::Indexer;
 use nav::Nav;
+use preview::Preview;
 use reader::{CsvReader, NestedString};
-use source::Source;
+use source::{DialectOpts, Source};
 use spinner::Spinner;
 use tui::{
     crossterm::event::{self, Event, KeyCode, KeyModifiers},
@@ -23,16 +25,21 @@ use tui::{
 };
 use ui::{FilterPrompt, Navigator};
 
+mod cache;
 mod cols;
 mod filter;
 mod fmt;
 mod histogram;
 mod index;
 mod nav;
+mod preview;
 mod prompt;
 mod reader;
+mod row_index;
+mod sniff;
 mod source;
 mod spinner;
+mod spool;
 mod style;
 mod ui;
 
@@ -40,10 +47,99 @@ mod ui;
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 pub const BUF_LEN: usize = 8 * 1024;
+/// Maximum number of previous filters kept to return to them
+const FILTER_STACK_LEN: usize = 8;
+/// Delay without typing before previewing a filter
+const PREVIEW_DEBOUNCE: Duration = Duration::from_millis(150);
 
 #[derive(clap::Parser, Debug)]
 pub struct Args {
     pub filename: Option<PathBuf>,
+    /// Field delimiter, sniffed from content if missing
+    #[arg(short, long, value_parser = parse_byte)]
+    pub delimiter: Option<u8>,
+    /// First row contains headers
+    #[arg(long, overrides_with = "no_header")]
+    pub header: bool,
+    /// First row contains data
+    #[arg(long, overrides_with = "header")]
+    pub no_header: bool,
+    /// Quote character
+    #[arg(long, value_parser = parse_byte)]
+    pub quote: Option<u8>,
+    /// Escape character, disabled by default
+    #[arg(long, value_parser = parse_byte)]
+    pub escape: Option<u8>,
+    /// Two adjacent quotes are interpreted as one escaped quote
+    #[arg(long, value_name = "BOOL")]
+    pub double_quote: Option<bool>,
+    /// Lines starting with this character are ignored
+    #[arg(long, value_parser = parse_byte)]
+    pub comment: Option<u8>,
+    /// Index appended content as the file grows
+    #[arg(short, long)]
+    pub follow: bool,
+    /// Do not cache file index on disk
+    #[arg(long)]
+    pub no_cache: bool,
+    /// Index cache location, the user cache dir by default
+    #[arg(long, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+    /// Map file in memory instead of reading it through a buffer, the file must not be
+    /// truncated or rewritten while open
+    #[arg(long)]
+    pub mmap: bool,
+    /// Date format of column content in strftime syntax, can be repeated
+    #[arg(long, value_name = "FORMAT")]
+    pub date_format: Vec<String>,
+    /// Time zone of dates without offset: local, utc or an offset like +02:00
+    #[arg(long, value_parser = Tz::parse, default_value = "local")]
+    pub tz: Tz,
+    /// Content considered null besides empty content, can be repeated
+    #[arg(long, value_name = "TOKEN")]
+    pub null: Vec<String>,
+}
+
+impl Args {
+    pub fn cache_dir(&self) -> Option<PathBuf> {
+        if self.no_cache {
+            None
+        } else {
+            self.cache_dir.clone().or_else(IndexCache::default_dir)
+        }
+    }
+
+    pub fn dialect(&self) -> DialectOpts {
+        DialectOpts {
+            delimiter: self.delimiter,
+            has_header: match (self.header, self.no_header) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            quote: self.quote,
+            escape: self.escape,
+            double_quote: self.double_quote,
+            comment: self.comment,
+        }
+    }
+
+    pub fn filter_config(&self) -> Config {
+        let dates = DateConfig::new(self.date_format.clone(), self.tz);
+        Config::new(dates, self.null.clone())
+    }
+}
+
+/// Parse a single ASCII character argument, accepting common escaped forms
+fn parse_byte(arg: &str) -> Result<u8, String> {
+    match arg {
+        "\\t" | "tab" => Ok(b'\t'),
+        "space" => Ok(b' '),
+        _ => match arg.as_bytes() {
+            [c] if c.is_ascii() => Ok(*c),
+            _ => Err(format!("expected a single ASCII character, got '{arg}'")),
+        },
+    }
 }
 
 pub fn nb_print_len(nb: usize) -> usize {
@@ -52,7 +148,21 @@ pub fn nb_print_len(nb: usize) -> usize {
 
 fn main() {
     let args = Args::parse();
-    let mut app = App::open(args.filename).unwrap();
+    let app = Source::new(
+        args.filename.clone(),
+        args.dialect(),
+        args.follow,
+        args.cache_dir(),
+        args.mmap,
+    )
+    .and_then(|(source, rdr)| App::open(source, rdr, args.filter_config()));
+    let mut app = match app {
+        Ok(app) => app,
+        Err(e) => {
+            eprintln!("csvex: {e}");
+            std::process::exit(1);
+        }
+    };
     let mut redraw = true;
     let mut terminal = Terminal::new(io::stdout()).unwrap();
     loop {
@@ -65,6 +175,7 @@ fn main() {
         if event::poll(Duration::from_millis(250)).unwrap() {
             loop {
                 if app.on_event(event::read().unwrap()) {
+                    app.indexer.close();
                     return;
                 }
                 // Ingest more event before drawing if we can
@@ -82,17 +193,28 @@ fn main() {
 
 enum AppState {
     Normal,
-    Filter { show_off: bool },
+    /// Refined filters apply on top of the current rows
+    Filter {
+        show_off: bool,
+        refine: bool,
+    },
     Size,
     Nav(Navigator),
     Histogram(Histographer),
 }
 
+/// Rows of a previously applied filter
+enum Previous {
+    Indexed(Indexer),
+    /// Filter whose indexing was canceled when replaced, run again when returned to
+    Canceled(Box<Filter>),
+}
+
 enum GridType<'a> {
     Normal {
         id_len: usize,
         cols: Vec<(usize, Vec<(Ty, &'a BStr)>, ColStat, usize)>,
-        rows: &'a [(u32, NestedString)],
+        rows: &'a [(u64, NestedString)],
     },
     Histogram,
 }
@@ -103,59 +225,218 @@ struct App {
     grid: Grid,
     nav: Nav,
     indexer: Indexer,
+    /// Previously applied filters, most recent last
+    filters: Vec<Previous>,
     spinner: Spinner,
     fmt: Fmt,
     dirty: bool,
     err: String,
+    /// Record to move the cursor to once indexed
+    pending_row: Option<u64>,
     cols: Cols,
     state: AppState,
     filter_prompt: FilterPrompt,
+    /// Sample evaluation of the filter being typed
+    preview: Option<Preview>,
+    /// Filter to preview once typing pauses
+    pending_preview: Option<(Instant, Filter)>,
+    /// Values suggested by filter completion
+    top_values: Option<TopValues>,
+    /// Rows currently on screen with their offset
+    visible: Vec<(u64, u64)>,
+    /// Filter evaluation settings
+    config: Config,
 }
 
 impl App {
-    pub fn open(filename: Option<PathBuf>) -> io::Result<Self> {
-        let (source, rdr) = Source::new(filename)?;
+    pub fn open(source: Source, rdr: CsvReader, config: Config) -> io::Result<Self> {
         let (headers, index) = Indexer::index(&source, Filter::empty())?;
         Ok(Self {
             source,
             rdr,
             indexer: index,
+            filters: Vec::new(),
             grid: Grid::new(),
             nav: Nav::new(),
             spinner: Spinner::new(),
             fmt: Fmt::new(),
             dirty: false,
             err: String::new(),
+            pending_row: None,
             cols: Cols::new(headers),
             filter_prompt: FilterPrompt::new(),
+            preview: None,
+            pending_preview: None,
+            top_values: None,
+            visible: Vec::new(),
             state: AppState::Normal,
+            config,
         })
     }
 
     pub fn is_loading(&self) -> bool {
         match &self.state {
             AppState::Histogram(h) => h.is_loading(),
+            AppState::Filter { .. }
+                if self.pending_preview.is_some()
+                    || self.filter_prompt.is_waiting()
+                    || self.preview.as_ref().is_some_and(Preview::is_loading) =>
+            {
+                true
+            }
             _ => self.indexer.is_loading(),
         }
     }
 
     pub fn refresh(&mut self) {
-        let rdr = self.source.refresh().unwrap();
-        let (headers, index) = Indexer::index(&self.source, Filter::empty()).unwrap();
+        if let Err(e) = self.try_refresh() {
+            self.err = format!("Reload failed: {e}");
+        }
+    }
+
+    fn try_refresh(&mut self) -> io::Result<()> {
+        self.pending_row = self
+            .indexer
+            .get_offsets(self.nav.c_row..self.nav.c_row + 1)
+            .first()
+            .map(|(row, _)| *row);
+        let rdr = self.source.refresh()?;
+        // Recompile the active filter against the new content, names follow reordered columns
+        // and indexes are checked against the new width
+        let (_, headers) = self.source.reader()?;
+        let nb_col = self.source.nb_col.max(headers.len());
+        let filter = match self.indexer.filter_string() {
+            Some(source) => match Filter::new(source, &headers, nb_col, &self.config) {
+                Ok(filter) => filter,
+                Err((_, msg)) => {
+                    self.err = format!("Filter dropped on reload: {msg}");
+                    Filter::empty()
+                }
+            },
+            None => Filter::empty(),
+        };
+        let (headers, index) = Indexer::index(&self.source, filter)?;
         self.rdr = rdr;
         self.indexer = index;
+        self.filters.clear();
+        self.top_values = None;
         self.cols.set_headers(headers);
         self.grid = Grid::new();
         self.dirty = false;
         if let AppState::Histogram(h) = &mut self.state {
             let (off, _) = self.cols.get_col(self.nav.c_col);
-            *h = Histographer::analyze(&self.source, off, self.indexer.filter().clone()).unwrap();
+            *h = Histographer::analyze(&self.source, off, self.indexer.filter().clone())?;
+        }
+        Ok(())
+    }
+
+    /// Combine a refinement with the current filter, telling if the result narrows it
+    fn refined(&self, filter: Filter, refine: bool) -> Result<(Filter, bool), String> {
+        match self.indexer.filter_string() {
+            Some(current) if refine && !filter.nodes.is_empty() => {
+                let source = format!("({current}) && ({})", filter.source);
+                Filter::new(
+                    &source,
+                    self.cols.headers(),
+                    self.cols.nb_col(),
+                    &self.config,
+                )
+                .map(|filter| (filter, true))
+                .map_err(|(_, msg)| format!("Refinement failed: {msg}"))
+            }
+            _ => {
+                let narrow = filter.narrows(self.indexer.filter());
+                Ok((filter, narrow))
+            }
+        }
+    }
+
+    /// Index rows matching a filter, only reading the current rows when it narrows their filter
+    fn apply_filter(&mut self, filter: Filter, refine: bool) {
+        let (filter, narrow) = match self.refined(filter, refine) {
+            Ok(refined) => refined,
+            Err(e) => {
+                self.err = e;
+                return;
+            }
+        };
+        let result = if narrow {
+            self.indexer.refine(&self.source, filter)
+        } else {
+            Indexer::index(&self.source, filter)
+        };
+        match result {
+            Ok((headers, index)) => {
+                let previous = std::mem::replace(&mut self.indexer, index);
+                // Unfinished indexing is canceled to not keep reading in the background
+                self.filters.push(if previous.is_loading() {
+                    Previous::Canceled(Box::new(previous.filter().clone()))
+                } else {
+                    Previous::Indexed(previous)
+                });
+                if self.filters.len() > FILTER_STACK_LEN {
+                    self.filters.remove(0);
+                }
+                self.cols.set_headers(headers);
+                self.filter_prompt.on_compile();
+            }
+            Err(e) => self.err = format!("Indexing failed: {e}"),
+        }
+    }
+
+    /// Edit the filter prompt, previewing the filter being typed or applying it
+    fn edit_filter(&mut self, code: KeyCode, refine: bool) {
+        let (source, apply) = if code == KeyCode::Tab {
+            // Suggest values of the compared column or of the current one
+            let current =
+                (self.cols.visible_col() > 0).then(|| self.cols.get_col(self.nav.c_col).0);
+            let source =
+                self.filter_prompt
+                    .complete(self.cols.headers(), self.cols.nb_col(), |col| {
+                        let Some(off) = col.or(current) else {
+                            return Some(Vec::new());
+                        };
+                        // Sample values in the background, the completion waits for them
+                        if self.top_values.as_ref().is_none_or(|top| top.col() != off) {
+                            match TopValues::sample(&self.source, off) {
+                                Ok(top) => self.top_values = Some(top),
+                                Err(e) => {
+                                    self.err = format!("Completion failed: {e}");
+                                    return Some(Vec::new());
+                                }
+                            }
+                        }
+                        self.top_values.as_ref().and_then(TopValues::values)
+                    });
+            (source, false)
+        } else {
+            self.filter_prompt.on_key(code)
+        };
+        // Cancel the preview of the previous content
+        self.preview = None;
+        self.pending_preview = None;
+        match Filter::new(
+            source,
+            self.cols.headers(),
+            self.cols.nb_col(),
+            &self.config,
+        ) {
+            Ok(filter) => {
+                if apply {
+                    self.apply_filter(filter, refine);
+                    self.state = AppState::Normal;
+                } else if !filter.nodes.is_empty() {
+                    self.pending_preview = Some((Instant::now(), filter));
+                }
+            }
+            Err(err) => self.filter_prompt.on_error(err, apply),
         }
     }
 
     pub fn on_event(&mut self, event: Event) -> bool {
         if let Event::Key(event) = event {
             self.err.clear();
+            self.pending_row = None;
 
             match &mut self.state {
                 AppState::Normal => match event.code {
@@ -178,38 +459,70 @@ impl App {
                         }
                         self.nav.right()
                     }
-                    KeyCode::Char('/') => self.state = AppState::Filter { show_off: true },
+                    KeyCode::Char('/') => {
+                        self.state = AppState::Filter {
+                            show_off: true,
+                            refine: false,
+                        }
+                    }
+                    KeyCode::Char('&') => {
+                        self.state = AppState::Filter {
+                            show_off: true,
+                            refine: true,
+                        }
+                    }
+                    KeyCode::Backspace => {
+                        // Return to the rows of the previous filter, on the same record
+                        if let Some(previous) = self.filters.pop() {
+                            self.pending_row = self
+                                .indexer
+                                .get_offsets(self.nav.c_row..self.nav.c_row + 1)
+                                .first()
+                                .map(|(row, _)| *row);
+                            match previous {
+                                Previous::Indexed(indexer) => self.indexer = indexer,
+                                Previous::Canceled(filter) => {
+                                    match Indexer::index(&self.source, *filter) {
+                                        Ok((headers, index)) => {
+                                            self.indexer = index;
+                                            self.cols.set_headers(headers);
+                                        }
+                                        Err(e) => self.err = format!("Indexing failed: {e}"),
+                                    }
+                                }
+                            }
+                        }
+                    }
                     KeyCode::Char('s') => self.state = AppState::Size,
                     KeyCode::Char('g') => {
                         self.state = AppState::Nav(Navigator::new(self.nav.clone()))
                     }
                     KeyCode::Char('f') => {
                         let (off, _) = self.cols.get_col(self.nav.c_col);
-                        self.state = AppState::Histogram(
-                            Histographer::analyze(&self.source, off, self.indexer.filter().clone())
-                                .unwrap(),
-                        )
+                        match Histographer::analyze(
+                            &self.source,
+                            off,
+                            self.indexer.filter().clone(),
+                        ) {
+                            Ok(h) => self.state = AppState::Histogram(h),
+                            Err(e) => self.err = format!("Analysis failed: {e}"),
+                        }
                     }
                     _ => {}
                 },
-                AppState::Filter { show_off } => match event.code {
-                    KeyCode::Esc => self.state = AppState::Normal,
-                    KeyCode::Tab => *show_off = !*show_off,
+                AppState::Filter { show_off, refine } => match event.code {
+                    KeyCode::Esc => {
+                        self.state = AppState::Normal;
+                        self.preview = None;
+                        self.pending_preview = None;
+                        self.filter_prompt.on_close();
+                    }
+                    KeyCode::Char('o') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        *show_off = !*show_off
+                    }
                     code => {
-                        let (source, apply) = self.filter_prompt.on_key(code);
-                        match Filter::new(source, self.cols.nb_col()) {
-                            Ok(filter) => {
-                                if apply {
-                                    let (headers, index) =
-                                        Indexer::index(&self.source, filter).unwrap();
-                                    self.indexer = index;
-                                    self.cols.set_headers(headers);
-                                    self.state = AppState::Normal;
-                                    self.filter_prompt.on_compile();
-                                }
-                            }
-                            Err(err) => self.filter_prompt.on_error(err, apply),
-                        }
+                        let refine = *refine;
+                        self.edit_filter(code, refine);
                     }
                 },
                 AppState::Size => {
@@ -256,7 +569,58 @@ impl App {
 
     pub fn draw(&mut self, c: &mut Canvas) {
         if !self.dirty {
-            self.dirty = self.source.check_dirty().unwrap();
+            self.dirty = self.source.check_dirty().unwrap_or_else(|e| {
+                self.err = format!("File watch failed: {e}");
+                false
+            });
+            // Followed file was truncated or rewritten
+            if self.dirty && self.source.is_following() {
+                self.refresh();
+            }
+        }
+        // Sniff stdin again once it wrote enough content
+        if self.source.should_resniff() {
+            self.refresh();
+        }
+
+        // Start the preview once typing pauses
+        if let AppState::Filter { refine, .. } = self.state {
+            // Complete once the values are sampled
+            if self.filter_prompt.is_waiting()
+                && self
+                    .top_values
+                    .as_ref()
+                    .is_some_and(|top| !top.is_loading())
+            {
+                self.edit_filter(KeyCode::Tab, refine);
+            }
+            if let Some((_, filter)) = self
+                .pending_preview
+                .take_if(|(since, _)| since.elapsed() >= PREVIEW_DEBOUNCE)
+            {
+                let preview = self.refined(filter, refine).and_then(|(filter, _)| {
+                    Preview::run(&self.source, filter, self.visible.clone())
+                        .map_err(|e| format!("Preview failed: {e}"))
+                });
+                match preview {
+                    Ok(preview) => self.preview = Some(preview),
+                    Err(e) => self.err = e,
+                }
+            }
+        }
+
+        // Report background tasks failures
+        let bg_err = match &self.state {
+            AppState::Histogram(h) => h.error(),
+            AppState::Filter { .. } => self
+                .preview
+                .as_ref()
+                .and_then(Preview::error)
+                .or_else(|| self.top_values.as_ref().and_then(TopValues::error)),
+            _ => None,
+        };
+        if let Some(err) = bg_err.or_else(|| self.indexer.error()) {
+            self.err = err;
         }
 
         let w = c.width();
@@ -271,7 +635,16 @@ impl App {
 
         // Draw prompt
         match &self.state {
-            AppState::Filter { .. } => self.filter_prompt.draw_prompt(c),
+            AppState::Filter { refine, .. } => {
+                self.filter_prompt
+                    .draw_prompt(c, self.cols.headers(), self.cols.nb_col(), *refine);
+                if let Some(preview) = &self.preview {
+                    let cols: Vec<_> = (0..self.cols.visible_col())
+                        .map(|idx| self.cols.get_col(idx).0)
+                        .collect();
+                    preview.draw(c, &mut self.fmt, &cols);
+                }
+            }
             AppState::Nav(navigator) => {
                 navigator.draw_prompt(c);
             }
@@ -291,14 +664,35 @@ impl App {
                 let nb_row = self.indexer.nb_row();
                 self.cols.set_nb_cols(nb_col);
                 let visible_cols = self.cols.visible_col();
+                // Restore cursor position after reload
+                if let Some(row) = self.pending_row {
+                    match self.indexer.find_row(row) {
+                        Ok(pos) => {
+                            nav.c_row = pos;
+                            self.pending_row = None;
+                        }
+                        Err(pos) if !self.indexer.is_loading() => {
+                            nav.c_row = pos;
+                            self.pending_row = None;
+                        }
+                        Err(_) => {}
+                    }
+                }
+                // Stick to the last row when following appended content
+                if self.source.is_following() && nav.c_row >= nav.m_row {
+                    nav.c_row = nb_row.saturating_sub(1);
+                }
                 // Get rows content
                 let row_off = nav.row_offset(nb_row, nb_draw_row);
                 let offsets = self.indexer.get_offsets(row_off..row_off + nb_draw_row);
-                self.grid.read_rows(&offsets, &mut self.rdr).unwrap();
+                if let Err(e) = self.grid.read_rows(&offsets, &mut self.rdr) {
+                    self.err = format!("Read failed: {e}");
+                }
+                self.visible = offsets;
                 let rows = self.grid.rows();
                 let id_len = rows
                     .last()
-                    .map(|(i, _)| (*i as f32 + 1.).log10() as usize + 1)
+                    .map(|(i, _)| (*i as f64 + 1.).log10() as usize + 1)
                     .unwrap_or(1);
                 let mut remain_table_w = c.width() - id_len as usize - 1;
                 let mut cols = Vec::new();
@@ -361,16 +755,44 @@ impl App {
         }
 
         if self.cols.nb_col() > 0 {
-            let (_, name) = self.cols.get_col(self.nav.c_col);
+            let (off, name) = self.cols.get_col(self.nav.c_col);
+            if let Some(ty) = self.indexer.col_type(off) {
+                l.rdraw(format_args!(" {}", ty.name()), style::secondary());
+            }
             l.rdraw(name, style::primary());
             l.rdraw(" ", style::primary());
         }
 
+        if self.source.is_following() {
+            l.rdraw(" FOLLOW ", style::progress());
+            l.rdraw(" ", style::primary());
+        } else if self.source.is_streaming() {
+            let nb_row = self.fmt.amount(self.indexer.nb_row());
+            l.rdraw(format_args!("{nb_row} rows "), style::primary());
+            l.rdraw(" STREAM ", style::progress());
+            l.rdraw(" ", style::primary());
+        }
+
+        if let Some(confidence) = self.source.confidence {
+            let percent = confidence * 100.;
+            l.rdraw(
+                format_args!("{} {percent:.0}% ", self.source.dialect),
+                style::secondary(),
+            );
+        } else {
+            l.rdraw(format_args!("{} ", self.source.dialect), style::secondary());
+        }
+
         match &self.state {
             AppState::Nav(navigator) => navigator.draw_status(&mut l, &mut self.fmt),
             _ => {
                 if let Some(filter) = self.indexer.filter_string() {
-                    FilterPrompt::draw_status(&mut l, filter)
+                    FilterPrompt::draw_status(
+                        &mut l,
+                        filter,
+                        self.cols.headers(),
+                        self.cols.nb_col(),
+                    )
                 } else {
                     l.draw(&self.source.display_path, style::progress());
                 }
@@ -380,7 +802,7 @@ impl App {
         match ty {
             GridType::Normal { id_len, cols, rows } => {
                 // Draw headers
-                let show_off = matches!(self.state, AppState::Filter { show_off: true });
+                let show_off = matches!(self.state, AppState::Filter { show_off: true, .. });
                 let nav = match &mut self.state {
                     AppState::Nav(navigator) => navigator.nav(),
                     _ => &mut self.nav,
@@ -447,7 +869,7 @@ impl App {
 
 struct Grid {
     /// Rows metadata
-    rows: Vec<(u32, NestedString)>,
+    rows: Vec<(u64, NestedString)>,
     /// Number of fresh rows
     len: usize,
 }
@@ -460,7 +882,7 @@ impl Grid {
         }
     }
 
-    pub fn read_rows(&mut self, rows: &[(u32, u64)], rdr: &mut CsvReader) -> io::Result<()> {
+    pub fn read_rows(&mut self, rows: &[(u64, u64)], rdr: &mut CsvReader) -> io::Result<()> {
         self.len = 0;
         for (row, offset) in rows {
             self.read_row(*row, *offset, rdr)?;
@@ -468,7 +890,7 @@ impl Grid {
         Ok(())
     }
 
-    fn read_row(&mut self, line: u32, offset: u64, rdr: &mut CsvReader) -> io::Result<()> {
+    fn read_row(&mut self, line: u64, offset: u64, rdr: &mut CsvReader) -> io::Result<()> {
         if self.len == self.rows.len() {
             let mut nested = NestedString::new();
             rdr.record_at(&mut nested, offset)?;
@@ -484,7 +906,7 @@ impl Grid {
         Ok(())
     }
 
-    pub fn rows(&self) -> &[(u32, NestedString)] {
+    pub fn rows(&self) -> &[(u64, NestedString)] {
         &self.rows[..self.len]
     }
 }
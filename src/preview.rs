@@ -0,0 +1,165 @@
+use std::{
+    collections::HashSet,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Arc,
+    },
+    thread,
+};
+
+use bstr::{BStr, ByteSlice};
+use parking_lot::Mutex;
+use tui::Canvas;
+
+use crate::{
+    filter::{Engine, Filter},
+    fmt::Fmt,
+    nb_print_len,
+    reader::{located, CsvReader, NestedString},
+    source::Source,
+    style,
+};
+
+/// Number of records read from the start of the file
+const PREVIEW_LEN: usize = 10_000;
+/// Maximum number of matching rows shown
+const PREVIEW_ROWS: usize = 5;
+/// Maximum width of a field in a preview row
+const FIELD_LEN: usize = 24;
+
+struct State {
+    nb_read: AtomicUsize,
+    nb_match: AtomicUsize,
+    /// First matching rows by record number
+    rows: Mutex<Vec<(u64, NestedString)>>,
+    error: Mutex<Option<String>>,
+}
+
+/// Filter evaluation over a sample of the file, the visible rows then the start of the file
+pub struct Preview {
+    state: Arc<State>,
+}
+
+impl Preview {
+    pub fn run(source: &Source, filter: Filter, visible: Vec<(u64, u64)>) -> io::Result<Self> {
+        // Only read spooled content to never wait for streamed data
+        let (rdr, _) = source.written_reader()?;
+        let partial = source.spool().is_some_and(|spool| !spool.is_done());
+        let state = Arc::new(State {
+            nb_read: AtomicUsize::new(0),
+            nb_match: AtomicUsize::new(0),
+            rows: Mutex::new(Vec::new()),
+            error: Mutex::new(None),
+        });
+
+        {
+            let state = state.clone();
+            thread::spawn(move || {
+                if let Err(e) = Self::bg_preview(rdr, filter, visible, partial, &state) {
+                    *state.error.lock() = Some(format!("Preview failed: {e}"));
+                }
+            });
+        }
+
+        Ok(Self { state })
+    }
+
+    fn bg_preview(
+        mut rdr: CsvReader,
+        filter: Filter,
+        visible: Vec<(u64, u64)>,
+        partial: bool,
+        state: &Arc<State>,
+    ) -> io::Result<()> {
+        let engine = Engine::new(&filter);
+        let mut record = NestedString::new();
+        let check = |record: &mut NestedString, row: u64, pos: u64| {
+            state.nb_read.fetch_add(1, Relaxed);
+            if engine.check(record, row, pos) {
+                state.nb_match.fetch_add(1, Relaxed);
+                let mut rows = state.rows.lock();
+                let idx = rows.partition_point(|(prev, _)| *prev < row);
+                if idx < PREVIEW_ROWS {
+                    rows.insert(idx, (row, std::mem::replace(record, NestedString::new())));
+                    rows.truncate(PREVIEW_ROWS);
+                }
+            }
+        };
+
+        // Rows on screen first
+        let mut pos = rdr.pos()?;
+        for (row, offset) in &visible {
+            rdr.record_at(&mut record, *offset)
+                .map_err(|e| located(e, *offset))?;
+            check(&mut record, *row, *offset);
+        }
+        if Arc::strong_count(state) == 1 {
+            return Ok(());
+        }
+
+        // Then the start of the file, without counting visible rows twice
+        let visible: HashSet<u64> = visible.into_iter().map(|(row, _)| row).collect();
+        rdr.seek(pos)?;
+        for row in 0..PREVIEW_LEN as u64 {
+            // The last record of a partial stream may be cut
+            let amount = if partial {
+                rdr.complete_record(&mut record)
+            } else {
+                rdr.record(&mut record)
+            }
+            .map_err(|e| located(e, pos))?;
+            if amount == 0 {
+                break;
+            } else if Arc::strong_count(state) == 1 {
+                return Ok(());
+            }
+            if !visible.contains(&row) {
+                check(&mut record, row, pos);
+            }
+            pos += amount as u64;
+        }
+        Ok(())
+    }
+
+    // Check if the preview is working in the background
+    pub fn is_loading(&self) -> bool {
+        Arc::strong_count(&self.state) > 1
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.state.error.lock().clone()
+    }
+
+    /// Draw the matching rows and the match count above the prompt, showing the columns at
+    /// these offsets
+    pub fn draw(&self, c: &mut Canvas, fmt: &mut Fmt, cols: &[usize]) {
+        let nb_read = self.state.nb_read.load(Relaxed);
+        let nb_match = self.state.nb_match.load(Relaxed);
+        let mut l = c.btm();
+        l.draw(format_args!("{} ", fmt.amount(nb_match)), style::progress());
+        l.draw("matches in ", style::secondary());
+        l.draw(format_args!("{} ", fmt.amount(nb_read)), style::primary());
+        l.draw("sampled rows", style::secondary());
+
+        let rows = self.state.rows.lock();
+        let id_len = rows
+            .iter()
+            .map(|(row, _)| nb_print_len(*row as usize + 1))
+            .max()
+            .unwrap_or(1);
+        let nb_line = rows.len().min(c.height() / 3);
+        // Drawn from the bottom, in row order from the top
+        for (row, record) in rows[..nb_line].iter().rev() {
+            let mut l = c.btm();
+            l.draw(format_args!("{:>1$} ", row + 1, id_len), style::secondary());
+            for (i, off) in cols.iter().enumerate() {
+                if i > 0 {
+                    l.draw("│", style::separator());
+                }
+                let field = BStr::new(record.get(*off).unwrap_or_default().trim());
+                l.draw(fmt.rtrim(field, FIELD_LEN), style::primary());
+            }
+        }
+    }
+}
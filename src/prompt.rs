@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use reedline::LineBuffer;
 
 struct HistoryBuffer<T, const N: usize> {
@@ -96,6 +98,13 @@ impl Prompt {
                 self.buffer.clear();
             }
             PromptCmd::Jump(pos) => self.buffer.set_insertion_point(pos),
+            PromptCmd::Replace(range, str) => {
+                self.solidify();
+                let mut buffer = self.buffer.get_buffer().to_string();
+                buffer.replace_range(range.clone(), &str);
+                self.buffer.set_buffer(buffer);
+                self.buffer.set_insertion_point(range.start + str.len());
+            }
         }
     }
 
@@ -119,4 +128,6 @@ pub enum PromptCmd {
     New(bool),
     Delete,
     Jump(usize),
+    /// Replace content and move the cursor after it
+    Replace(Range<usize>, String),
 }
@@ -1,30 +1,107 @@
 use std::{
-    fs::File,
+    fmt::Display,
     io::{self, BufRead, BufReader, Seek},
     ops::{Deref, DerefMut, Range},
+    sync::Arc,
 };
 
 use bstr::{BStr, ByteSlice};
 use csv_core::ReadRecordResult;
+use memmap2::Mmap;
 
-use crate::BUF_LEN;
+use crate::{spool::Input, BUF_LEN};
+
+/// CSV format settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub escape: Option<u8>,
+    pub double_quote: bool,
+    pub comment: Option<u8>,
+}
+
+impl Display for Dialect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.delimiter {
+            b'\t' => f.write_str("TAB"),
+            b' ' => f.write_str("SPACE"),
+            c => write!(f, "'{}'", c as char),
+        }
+    }
+}
+
+impl Dialect {
+    pub(crate) fn reader(&self) -> csv_core::Reader {
+        csv_core::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .escape(self.escape)
+            .double_quote(self.double_quote)
+            .comment(self.comment)
+            .build()
+    }
+}
+
+enum Backend {
+    Buffered(BufReader<Input>),
+    /// Whole file mapping shared between readers, random access is a pointer offset
+    Mapped {
+        map: Arc<Mmap>,
+        pos: usize,
+    },
+}
 
 pub struct CsvReader {
-    file: BufReader<File>,
+    backend: Backend,
+    dialect: Dialect,
     rdr: csv_core::Reader,
 }
 
 impl CsvReader {
-    pub(crate) fn new(file: BufReader<File>, delimiter: u8) -> Self {
+    pub(crate) fn new(file: BufReader<Input>, dialect: &Dialect) -> Self {
+        Self {
+            backend: Backend::Buffered(file),
+            dialect: *dialect,
+            rdr: dialect.reader(),
+        }
+    }
+
+    pub(crate) fn mapped(map: Arc<Mmap>, dialect: &Dialect) -> Self {
         Self {
-            file,
-            rdr: csv_core::ReaderBuilder::new().delimiter(delimiter).build(),
+            backend: Backend::Mapped { map, pos: 0 },
+            dialect: *dialect,
+            rdr: dialect.reader(),
         }
     }
 
     /// Read a record into a nested string
     pub fn record(&mut self, nested: &mut NestedString) -> io::Result<usize> {
-        nested.read_record(&mut self.file, &mut self.rdr)
+        self.read_terminated(nested).map(|(amount, _)| amount)
+    }
+
+    /// Read a complete record into a nested string, a record cut by the end of file is left
+    /// unread as more data may be appended later
+    pub fn complete_record(&mut self, nested: &mut NestedString) -> io::Result<usize> {
+        let pos = self.pos()?;
+        let (amount, terminated) = self.read_terminated(nested)?;
+        if terminated {
+            Ok(amount)
+        } else {
+            self.seek(pos)?;
+            Ok(0)
+        }
+    }
+
+    fn read_terminated(&mut self, nested: &mut NestedString) -> io::Result<(usize, bool)> {
+        match &mut self.backend {
+            Backend::Buffered(file) => nested.read_terminated(file, &mut self.rdr),
+            Backend::Mapped { map, pos } => {
+                let result = nested.read_mapped(map, *pos, &self.dialect, &mut self.rdr)?;
+                *pos += result.0;
+                Ok(result)
+            }
+        }
     }
 
     /// Read a record into a nested string from a random place in CSV file
@@ -34,19 +111,73 @@ impl CsvReader {
     }
 
     pub fn seek(&mut self, offset: u64) -> io::Result<()> {
-        let pos = self.file.stream_position()?; // syscall without disk read
-        self.file.seek_relative(offset as i64 - pos as i64)?; // keep buffer if close to current position
+        match &mut self.backend {
+            Backend::Buffered(file) => {
+                let pos = file.stream_position()?; // syscall without disk read
+                file.seek_relative(offset as i64 - pos as i64)?; // keep buffer if close to current position
+            }
+            Backend::Mapped { map, pos } => *pos = (offset as usize).min(map.len()),
+        }
         self.rdr.reset();
         Ok(())
     }
 
+    /// Skip content until the next line start, returning the number of skipped bytes
+    pub fn skip_line(&mut self) -> io::Result<u64> {
+        let mut skipped = 0;
+        match &mut self.backend {
+            Backend::Buffered(file) => loop {
+                let buff = file.fill_buf()?;
+                if buff.is_empty() {
+                    break;
+                } else if let Some(i) = buff.find_byte(b'\n') {
+                    file.consume(i + 1);
+                    skipped += i + 1;
+                    break;
+                } else {
+                    let amount = buff.len();
+                    file.consume(amount);
+                    skipped += amount;
+                }
+            },
+            Backend::Mapped { map, pos } => {
+                let remaining = &map[*pos..];
+                skipped = remaining
+                    .find_byte(b'\n')
+                    .map_or(remaining.len(), |i| i + 1);
+                *pos += skipped;
+            }
+        }
+        self.rdr.reset();
+        Ok(skipped as u64)
+    }
+
     pub fn pos(&mut self) -> io::Result<u64> {
-        self.file.stream_position()
+        match &mut self.backend {
+            Backend::Buffered(file) => file.stream_position(),
+            Backend::Mapped { pos, .. } => Ok(*pos as u64),
+        }
     }
 
     pub fn len(&self) -> io::Result<u64> {
-        Ok(self.file.get_ref().metadata()?.len())
+        match &self.backend {
+            Backend::Buffered(file) => file.get_ref().len(),
+            Backend::Mapped { map, .. } => Ok(map.len() as u64),
+        }
     }
+
+    /// Check if all buffered data have been consumed, the next read may wait for more data
+    pub fn is_drained(&self) -> bool {
+        match &self.backend {
+            Backend::Buffered(file) => file.buffer().is_empty(),
+            Backend::Mapped { .. } => false,
+        }
+    }
+}
+
+/// Add the failing position to a read error
+pub fn located(e: io::Error, pos: u64) -> io::Error {
+    io::Error::new(e.kind(), format!("{e} at byte {pos}"))
 }
 
 /// Byte vector that is backed by an always initialize slice so we can write in the currently
@@ -83,6 +214,13 @@ impl<T: Default + Copy, const N: usize> InitVec<T, N> {
         self.len = len;
     }
 
+    /// Append an element at the end
+    pub fn push(&mut self, item: T) {
+        self.advance(1);
+        let last = self.len - 1;
+        self.buff[last] = item;
+    }
+
     /// Augment capacity by N
     pub fn grow(&mut self) {
         let mut vec = std::mem::take(&mut self.buff).into_vec();
@@ -110,6 +248,9 @@ pub struct NestedString {
     buff: InitVec<u8, BUF_LEN>,
     /// End of each string in buf
     bounds: InitVec<usize, 50>,
+    /// Raw record borrowed from a mapping when it needs no unescaping, bounds are then the start
+    /// of each string followed by its delimiter
+    mapped: Option<Arc<Mmap>>,
 }
 
 impl NestedString {
@@ -119,23 +260,37 @@ impl NestedString {
         Self {
             buff: InitVec::new(),
             bounds,
+            mapped: None,
         }
     }
 
-    fn read_record(
+    pub(crate) fn read_record(
         &mut self,
-        file: &mut BufReader<File>,
+        file: &mut impl BufRead,
         rdr: &mut csv_core::Reader,
     ) -> io::Result<usize> {
+        self.read_terminated(file, rdr).map(|(nb_read, _)| nb_read)
+    }
+
+    /// Read a record and check if it was terminated before the end of input
+    fn read_terminated(
+        &mut self,
+        file: &mut impl BufRead,
+        rdr: &mut csv_core::Reader,
+    ) -> io::Result<(usize, bool)> {
         // Reset buffer
         self.buff.set_len(0);
-        self.bounds.set_len(1);
+        self.bounds.set_len(0);
+        self.bounds.push(0);
+        self.mapped = None;
 
         let mut nb_read = 0;
+        let mut terminated;
 
         // Read record
         loop {
             let buff = file.fill_buf()?;
+            terminated = !buff.is_empty();
             let (result, r_in, r_out, r_bound) =
                 rdr.read_record(buff, self.buff.unused(), self.bounds.unused());
             file.consume(r_in);
@@ -150,17 +305,64 @@ impl NestedString {
                 ReadRecordResult::Record | ReadRecordResult::End => break,
             }
         }
-        // Collapse empty column a the end
+        self.collapse_last();
+        Ok((nb_read, terminated))
+    }
+
+    /// Read a record from a mapping, borrowing the raw line when it contains no special chars
+    fn read_mapped(
+        &mut self,
+        map: &Arc<Mmap>,
+        pos: usize,
+        dialect: &Dialect,
+        rdr: &mut csv_core::Reader,
+    ) -> io::Result<(usize, bool)> {
+        let remaining = &map[pos..];
+        let Some(nl) = remaining.find_byte(b'\n') else {
+            return self.read_terminated(&mut &*remaining, rdr);
+        };
+        let line = remaining[..nl]
+            .strip_suffix(b"\r")
+            .unwrap_or(&remaining[..nl]);
+        let is_plain = !line.is_empty()
+            && dialect.comment != line.first().copied()
+            && !line
+                .iter()
+                .any(|c| *c == dialect.quote || *c == b'\r' || Some(*c) == dialect.escape);
+        if !is_plain {
+            return self.read_terminated(&mut &*remaining, rdr);
+        }
+
+        self.bounds.set_len(0);
+        self.bounds.push(pos);
+        for i in line.find_iter(&[dialect.delimiter]) {
+            self.bounds.push(pos + i + 1);
+        }
+        self.bounds.push(pos + line.len() + 1);
+        if !matches!(&self.mapped, Some(mapped) if Arc::ptr_eq(mapped, map)) {
+            self.mapped = Some(map.clone());
+        }
+        self.collapse_last();
+        rdr.reset();
+        Ok((nl + 1, true))
+    }
+
+    /// Collapse empty column a the end
+    fn collapse_last(&mut self) {
+        let sep = self.mapped.is_some() as usize;
         if self.bounds.len() > 2
-            && self.bounds[self.bounds.len() - 1] == self.bounds[self.bounds.len() - 2]
+            && self.bounds[self.bounds.len() - 1] - sep == self.bounds[self.bounds.len() - 2]
         {
             self.bounds.set_len(self.bounds.len() - 1)
         }
-        Ok(nb_read)
     }
 
     fn get_range(&self, range: Range<usize>) -> &BStr {
-        BStr::new(BStr::new(&self.buff[range]).trim())
+        let bytes = match &self.mapped {
+            Some(map) => &map[range.start..range.end - 1],
+            None => &self.buff[range],
+        };
+        BStr::new(BStr::new(bytes).trim())
     }
 
     pub fn get(&self, idx: usize) -> Option<&BStr> {
@@ -171,6 +373,14 @@ impl NestedString {
         self.bounds.len() - 1
     }
 
+    /// Content of all strings, separated by delimiters when borrowed from a mapping
+    pub fn raw(&self) -> &[u8] {
+        match &self.mapped {
+            Some(map) => &map[self.bounds[0]..self.bounds[self.bounds.len() - 1] - 1],
+            None => &self.buff,
+        }
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &BStr> {
         self.bounds.windows(2).map(|win| match win {
             [start, end] => self.get_range(*start..*end),
@@ -0,0 +1,295 @@
+use std::io::{self, Read, Write};
+
+const BLOCK_LEN: usize = 128;
+
+/// First entry of a packed block
+#[derive(Clone)]
+struct Checkpoint {
+    row: u64,
+    offset: u64,
+    /// Start of the block deltas in data
+    start: usize,
+    /// Rows of the block are consecutive, only offset deltas are stored
+    dense: bool,
+}
+
+/// Compact index of (row, offset) pairs, both strictly increasing
+///
+/// Entries are packed by blocks of varint encoded deltas with a checkpoint per block, an
+/// unfiltered index takes around two bytes per row.
+#[derive(Clone, Default)]
+pub struct RowIndex {
+    checkpoints: Vec<Checkpoint>,
+    data: Vec<u8>,
+    /// Last entries not packed yet
+    tail: Vec<(u64, u64)>,
+}
+
+impl RowIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.checkpoints.len() * BLOCK_LEN + self.tail.len()
+    }
+
+    pub fn push(&mut self, row: u64, offset: u64) {
+        self.tail.push((row, offset));
+        if self.tail.len() == BLOCK_LEN {
+            self.pack();
+        }
+    }
+
+    fn pack(&mut self) {
+        let (row, offset) = self.tail[0];
+        let dense = self.tail[BLOCK_LEN - 1].0 - row == BLOCK_LEN as u64 - 1;
+        self.checkpoints.push(Checkpoint {
+            row,
+            offset,
+            start: self.data.len(),
+            dense,
+        });
+        for win in self.tail.windows(2) {
+            if !dense {
+                write_varint(&mut self.data, win[1].0 - win[0].0 - 1);
+            }
+            write_varint(&mut self.data, win[1].1 - win[0].1);
+        }
+        self.tail.clear();
+    }
+
+    /// Decode a packed block
+    fn block(&self, idx: usize) -> impl Iterator<Item = (u64, u64)> + '_ {
+        let cp = &self.checkpoints[idx];
+        let mut data = &self.data[cp.start..];
+        let mut entry = (cp.row, cp.offset);
+        std::iter::once(entry).chain((1..BLOCK_LEN).map(move |_| {
+            entry = next_entry(&mut data, cp.dense, entry).expect("blocks are checked when read");
+            entry
+        }))
+    }
+
+    /// Iterate entries starting from the nth
+    pub fn iter_from(&self, n: usize) -> impl Iterator<Item = (u64, u64)> + '_ {
+        (n / BLOCK_LEN..self.checkpoints.len())
+            .flat_map(|idx| self.block(idx))
+            .chain(self.tail.iter().copied())
+            .skip(n % BLOCK_LEN)
+            .take(self.len().saturating_sub(n))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.iter_from(0)
+    }
+
+    /// Find the position of a row, or where it would be
+    pub fn find(&self, row: u64) -> Result<usize, usize> {
+        // Entries before the last block starting before row are all smaller
+        let start = self
+            .checkpoints
+            .partition_point(|cp| cp.row <= row)
+            .saturating_sub(1)
+            * BLOCK_LEN;
+        for (i, (r, _)) in self.iter_from(start).enumerate() {
+            if r == row {
+                return Ok(start + i);
+            } else if r > row {
+                return Err(start + i);
+            }
+        }
+        Err(self.len())
+    }
+
+    /// Write the packed blocks as is
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        write_u64(out, self.checkpoints.len() as u64)?;
+        for cp in &self.checkpoints {
+            for nb in [cp.row, cp.offset, cp.start as u64, cp.dense as u64] {
+                write_u64(out, nb)?;
+            }
+        }
+        write_u64(out, self.data.len() as u64)?;
+        out.write_all(&self.data)?;
+        write_u64(out, self.tail.len() as u64)?;
+        for (row, offset) in &self.tail {
+            write_u64(out, *row)?;
+            write_u64(out, *offset)?;
+        }
+        Ok(())
+    }
+
+    /// Read packed blocks, decoding them all once as iteration trusts them
+    pub fn read(input: &mut impl Read) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupted index");
+        let nb_checkpoint = read_u64(input)?;
+        let mut checkpoints = Vec::new();
+        for _ in 0..nb_checkpoint {
+            checkpoints.push(Checkpoint {
+                row: read_u64(input)?,
+                offset: read_u64(input)?,
+                start: read_u64(input)? as usize,
+                dense: read_u64(input)? != 0,
+            });
+        }
+        let mut data = Vec::new();
+        let len = read_u64(input)?;
+        input.take(len).read_to_end(&mut data)?;
+        if data.len() as u64 != len {
+            return Err(invalid());
+        }
+        // Blocks follow each other and decode to increasing entries without overflow
+        let mut remaining = data.as_slice();
+        for (i, cp) in checkpoints.iter().enumerate() {
+            if cp.start != data.len() - remaining.len() {
+                return Err(invalid());
+            }
+            let mut entry = (cp.row, cp.offset);
+            for _ in 1..BLOCK_LEN {
+                entry = next_entry(&mut remaining, cp.dense, entry).ok_or_else(invalid)?;
+            }
+            if checkpoints
+                .get(i + 1)
+                .is_some_and(|next| next.row <= entry.0)
+            {
+                return Err(invalid());
+            }
+        }
+        if !remaining.is_empty() {
+            return Err(invalid());
+        }
+        let nb_tail = read_u64(input)?;
+        if nb_tail >= BLOCK_LEN as u64 {
+            return Err(invalid());
+        }
+        let mut tail = Vec::new();
+        for _ in 0..nb_tail {
+            tail.push((read_u64(input)?, read_u64(input)?));
+        }
+        Ok(Self {
+            checkpoints,
+            data,
+            tail,
+        })
+    }
+}
+
+fn write_u64(out: &mut impl Write, nb: u64) -> io::Result<()> {
+    out.write_all(&nb.to_le_bytes())
+}
+
+pub fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn write_varint(data: &mut Vec<u8>, mut nb: u64) {
+    while nb >= 0x80 {
+        data.push(nb as u8 | 0x80);
+        nb >>= 7;
+    }
+    data.push(nb as u8);
+}
+
+/// Read a varint, None if it is cut or overflows
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut nb = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.first()?;
+        *data = data.get(1..)?;
+        let bits = (byte & 0x7f) as u64;
+        if shift > 63 || (shift == 63 && bits > 1) {
+            return None;
+        }
+        nb |= bits << shift;
+        if byte < 0x80 {
+            return Some(nb);
+        }
+        shift += 7;
+    }
+}
+
+/// Decode the entry following another in a block, None if data is corrupted
+fn next_entry(data: &mut &[u8], dense: bool, (row, offset): (u64, u64)) -> Option<(u64, u64)> {
+    let row_delta = match dense {
+        true => 1,
+        false => read_varint(data)?.checked_add(1)?,
+    };
+    let offset_delta = read_varint(data)?;
+    Some((
+        row.checked_add(row_delta)?,
+        offset.checked_add(offset_delta)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entries crossing block boundaries, with dense and sparse blocks and multi-byte deltas
+    fn sample() -> Vec<(u64, u64)> {
+        let (mut row, mut offset) = (0, 0);
+        (0..BLOCK_LEN as u64 * 4 + 17)
+            .map(|i| {
+                row += if (BLOCK_LEN as u64..BLOCK_LEN as u64 * 2).contains(&i) {
+                    1
+                } else {
+                    1 + i % 3 * 1000
+                };
+                offset += 1 + (i * 7919) % 300_000 + if i == 300 { u32::MAX as u64 } else { 0 };
+                (row, offset)
+            })
+            .collect()
+    }
+
+    fn written(entries: &[(u64, u64)]) -> Vec<u8> {
+        let mut index = RowIndex::new();
+        for (row, offset) in entries {
+            index.push(*row, *offset);
+        }
+        let mut out = Vec::new();
+        index.write(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries = sample();
+        let index = RowIndex::read(&mut written(&entries).as_slice()).unwrap();
+        assert_eq!(index.iter().collect::<Vec<_>>(), entries);
+        for (i, (row, _)) in entries.iter().enumerate() {
+            assert_eq!(index.find(*row), Ok(i));
+        }
+        assert_eq!(
+            index.iter_from(BLOCK_LEN * 2 - 1).next(),
+            Some(entries[BLOCK_LEN * 2 - 1])
+        );
+    }
+
+    #[test]
+    fn reject_corrupted() {
+        let bytes = written(&sample());
+        for len in 0..bytes.len() {
+            assert!(RowIndex::read(&mut &bytes[..len]).is_err(), "cut at {len}");
+        }
+        // Offset of the data length, after the checkpoints count and four fields per checkpoint
+        let data_at = 8 + 4 * 8 * 4;
+        let data_len = u64::from_le_bytes(bytes[data_at..data_at + 8].try_into().unwrap());
+        let data = data_at + 8..data_at + 8 + data_len as usize;
+        // Shorter block data
+        let mut cut = bytes.clone();
+        cut[data_at..data_at + 8].copy_from_slice(&(data_len - 1).to_le_bytes());
+        cut.remove(data.end - 1);
+        assert!(RowIndex::read(&mut cut.as_slice()).is_err());
+        // Overflowing varints
+        let mut overflow = bytes.clone();
+        overflow[data.start..data.start + 11].fill(0xff);
+        assert!(RowIndex::read(&mut overflow.as_slice()).is_err());
+        // Continuation bit on the last data byte
+        let mut unterminated = bytes;
+        unterminated[data.end - 1] |= 0x80;
+        assert!(RowIndex::read(&mut unterminated.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufReader, Seek, SeekFrom},
+    mem::discriminant,
+};
+
+use crate::{
+    fmt::Ty,
+    reader::{Dialect, NestedString},
+    source::DialectOpts,
+    spool::Input,
+};
+
+pub const SAMPLE_LEN: usize = 64 * 1024;
+const SAMPLE_RECORDS: usize = 100;
+/// Common delimiters by order of preference
+const DELIMITERS: [u8; 6] = [b',', b'\t', b';', b'|', b':', b' '];
+
+/// Dialect guessed from the beginning of the content
+pub struct Sniffed {
+    pub dialect: Dialect,
+    pub has_header: bool,
+    /// Confidence in the guessed delimiter between 0 and 1, None if provided by the user
+    pub confidence: Option<f32>,
+    /// Number of fields of the widest sampled record
+    pub nb_col: usize,
+    /// Sample holds the whole content
+    pub is_complete: bool,
+}
+
+/// Guess missing dialect settings from a sample of the first records
+pub fn sniff(file: &mut BufReader<Input>, opts: &DialectOpts) -> io::Result<Sniffed> {
+    // Last record may be truncated if the sample does not contain the whole content
+    let (sample, is_complete) = file.get_mut().sample(SAMPLE_LEN)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut dialect = Dialect {
+        delimiter: b',',
+        quote: opts.quote.unwrap_or(b'"'),
+        escape: opts.escape,
+        double_quote: opts.double_quote.unwrap_or(true),
+        comment: opts.comment,
+    };
+    let confidence = match opts.delimiter {
+        Some(delimiter) => {
+            dialect.delimiter = delimiter;
+            None
+        }
+        None => {
+            let (delimiter, confidence) = sniff_delimiter(&sample, is_complete, dialect);
+            dialect.delimiter = delimiter;
+            Some(confidence)
+        }
+    };
+    let records = parse(&sample, is_complete, &dialect);
+    let has_header = match opts.has_header {
+        Some(has_header) => has_header,
+        None => sniff_has_header(&records),
+    };
+    Ok(Sniffed {
+        dialect,
+        has_header,
+        confidence,
+        nb_col: records.iter().map(NestedString::len).max().unwrap_or(0),
+        is_complete,
+    })
+}
+
+fn parse(mut sample: &[u8], is_complete: bool, dialect: &Dialect) -> Vec<NestedString> {
+    let mut rdr = dialect.reader();
+    let mut records = Vec::new();
+    while records.len() < SAMPLE_RECORDS {
+        let mut record = NestedString::new();
+        let amount = record.read_record(&mut sample, &mut rdr).unwrap_or(0);
+        if amount == 0 || (sample.is_empty() && !is_complete) {
+            break;
+        }
+        records.push(record);
+    }
+    records
+}
+
+/// Pick the delimiter producing the most consistent field count across records
+fn sniff_delimiter(sample: &[u8], is_complete: bool, mut dialect: Dialect) -> (u8, f32) {
+    // Any punctuation of the first line could be an uncommon delimiter
+    let first_line = sample.split(|c| *c == b'\n').next().unwrap_or_default();
+    let mut candidates = DELIMITERS.to_vec();
+    for c in first_line {
+        if c.is_ascii_punctuation()
+            && !candidates.contains(c)
+            && *c != dialect.quote
+            && Some(*c) != dialect.escape
+            && Some(*c) != dialect.comment
+        {
+            candidates.push(*c);
+        }
+    }
+
+    let mut best = (DELIMITERS[0], 0., 0);
+    let mut record = NestedString::new();
+    for (i, delimiter) in candidates.into_iter().enumerate() {
+        dialect.delimiter = delimiter;
+        let mut rdr = dialect.reader();
+        let mut input = sample;
+        let mut counts = Vec::new();
+        while counts.len() < SAMPLE_RECORDS {
+            let amount = record.read_record(&mut input, &mut rdr).unwrap_or(0);
+            if amount == 0 || (input.is_empty() && !is_complete) {
+                break;
+            }
+            counts.push(record.len());
+        }
+        // Find the most common field count
+        let mut freqs = HashMap::new();
+        for count in &counts {
+            *freqs.entry(*count).or_insert(0) += 1;
+        }
+        let (modal, freq) = freqs
+            .into_iter()
+            .max_by_key(|(count, freq)| (*freq, *count))
+            .unwrap_or((0, 0));
+        if modal <= 1 {
+            continue;
+        }
+        let consistency = freq as f32 / counts.len() as f32;
+        // Favor common delimiters, space is often found in text content
+        let weight = if i < DELIMITERS.len() - 1 { 1. } else { 0.9 };
+        let (_, best_score, best_modal) = best;
+        let score = consistency * weight;
+        if score > best_score || (score == best_score && modal > best_modal) {
+            best = (delimiter, score, modal);
+        }
+    }
+    (best.0, best.1)
+}
+
+/// Guess if the first record is a header by checking its agreement with the column types
+fn sniff_has_header(records: &[NestedString]) -> bool {
+    let Some((header, rows)) = records.split_first() else {
+        return false;
+    };
+    if rows.is_empty() {
+        return false;
+    }
+
+    // Headers should be unique and not empty
+    let mut names = HashSet::new();
+    let mut found_empty = false;
+    for field in header.iter() {
+        if field.is_empty() {
+            if found_empty {
+                return false;
+            }
+            // Last column can be a fake one
+            found_empty = true;
+        } else if !names.insert(field) {
+            return false;
+        }
+    }
+
+    let mut votes = 0;
+    for (i, name) in header.iter().enumerate() {
+        let fields: Vec<_> = rows.iter().filter_map(|r| r.get(i)).collect();
+        let tys: Vec<_> = fields.iter().map(|f| Ty::guess(f)).collect();
+        let Some(first) = tys.first() else {
+            continue;
+        };
+        // Only consider columns with an agreed type
+        let agree = tys
+            .iter()
+            .filter(|ty| discriminant(*ty) == discriminant(first))
+            .count();
+        if agree * 10 < tys.len() * 9 {
+            continue;
+        }
+        if first.is_str() {
+            // Fixed length string column
+            let len = fields[0].len();
+            if fields.iter().all(|f| f.len() == len) && name.len() != len {
+                votes += 1;
+            }
+        } else if discriminant(&Ty::guess(name)) != discriminant(first) {
+            votes += 1;
+        } else {
+            votes -= 1;
+        }
+    }
+    votes > 0
+}
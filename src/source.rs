@@ -1,17 +1,62 @@
 use std::{
     borrow::Cow,
     fs::File,
-    io::{self, BufRead, BufReader, Seek, SeekFrom},
-    path::PathBuf,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
     time::{Duration, Instant, SystemTime},
 };
 
+use memmap2::Mmap;
+
 use crate::{
-    fmt::Ty,
-    reader::{CsvReader, NestedString},
+    cache::IndexCache,
+    reader::{CsvReader, Dialect, NestedString},
+    sniff::{sniff, Sniffed, SAMPLE_LEN},
+    spool::{Input, Spool},
 };
 
 pub const WATCHER_POOL: Duration = Duration::from_secs(1);
+const FINGERPRINT_LEN: u64 = 256;
+
+#[derive(Debug, Clone, Copy)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// Detect compression format from the file magic bytes
+    fn sniff(file: &mut File) -> io::Result<Option<Self>> {
+        let mut magic = [0; 6];
+        let mut len = 0;
+        while len < magic.len() {
+            match file.read(&mut magic[len..])? {
+                0 => break,
+                amount => len += amount,
+            }
+        }
+        file.seek(SeekFrom::Start(0))?;
+        Ok(match &magic[..len] {
+            [0x1f, 0x8b, ..] => Some(Self::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Self::Zstd),
+            [b'B', b'Z', b'h', ..] => Some(Self::Bzip2),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00] => Some(Self::Xz),
+            _ => None,
+        })
+    }
+
+    fn decoder(self, input: impl Read + Send + 'static) -> io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(input)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(input)?),
+            Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(input)),
+            Compression::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(input)),
+        })
+    }
+}
 
 enum SourceKind {
     File {
@@ -19,77 +64,278 @@ enum SourceKind {
         last: Instant,
         m_time: SystemTime,
     },
+    Compressed {
+        path: PathBuf,
+        last: Instant,
+        m_time: SystemTime,
+        tmp: tempfile::NamedTempFile,
+        spool: Spool,
+    },
     Stdin {
         tmp: tempfile::NamedTempFile,
+        spool: Spool,
     },
 }
 
 impl SourceKind {
+    pub fn from_path(path: PathBuf) -> io::Result<Self> {
+        let mut file = File::open(&path)?;
+        let metadata = file.metadata()?;
+        let m_time = metadata.modified()?;
+        Ok(match Compression::sniff(&mut file)? {
+            Some(compression) => {
+                // Decompress into a seekable spill file
+                let tmp = tempfile::NamedTempFile::new()?;
+                let spool = Spool::new(Some(metadata.len()));
+                let decoder = compression.decoder(spool.track(file))?;
+                spool.spawn(decoder, tmp.reopen()?);
+                SourceKind::Compressed {
+                    path,
+                    last: Instant::now(),
+                    m_time,
+                    tmp,
+                    spool,
+                }
+            }
+            None => SourceKind::File {
+                path,
+                last: Instant::now(),
+                m_time,
+            },
+        })
+    }
+
     pub fn path(&self) -> Cow<str> {
         match &self {
-            SourceKind::File { path, .. } => path.to_string_lossy(),
+            SourceKind::File { path, .. } | SourceKind::Compressed { path, .. } => {
+                path.to_string_lossy()
+            }
             SourceKind::Stdin { .. } => "stdin".into(),
         }
     }
 
-    pub fn open(&self) -> io::Result<File> {
-        match &self {
-            SourceKind::File { path, .. } => std::fs::File::open(path),
-            SourceKind::Stdin { tmp } => std::fs::File::open(tmp.path()),
+    pub fn open(&self) -> io::Result<Input> {
+        Ok(match &self {
+            SourceKind::File { path, .. } => Input::file(File::open(path)?),
+            SourceKind::Compressed { tmp, spool, .. } => {
+                Input::spooled(File::open(tmp.path())?, spool.clone())
+            }
+            SourceKind::Stdin { tmp, spool } => {
+                Input::spooled(File::open(tmp.path())?, spool.clone())
+            }
+        })
+    }
+
+    /// Open the content written so far, reads end instead of waiting for the spool
+    pub fn open_written(&self) -> io::Result<Input> {
+        Ok(match &self {
+            SourceKind::File { path, .. } => Input::file(File::open(path)?),
+            SourceKind::Compressed { tmp, .. } | SourceKind::Stdin { tmp, .. } => {
+                Input::file(File::open(tmp.path())?)
+            }
+        })
+    }
+
+    /// Sniff the content, stdin is sniffed on what it wrote so far to not wait for it, with if
+    /// it should be sniffed again once it wrote more
+    fn sniff_content(&self, opts: &DialectOpts) -> io::Result<(Sniffed, bool)> {
+        match self {
+            SourceKind::Stdin { spool, .. } => {
+                let done = spool.is_done();
+                let sniffed = sniff(&mut BufReader::new(self.open_written()?), opts)?;
+                let partial = !done && sniffed.is_complete;
+                Ok((sniffed, partial))
+            }
+            _ => Ok((sniff(&mut BufReader::new(self.open()?), opts)?, false)),
         }
     }
 }
 
+/// User provided format settings, missing ones are sniffed from the content
+#[derive(Debug, Clone, Default)]
+pub struct DialectOpts {
+    pub delimiter: Option<u8>,
+    pub has_header: Option<bool>,
+    pub quote: Option<u8>,
+    pub escape: Option<u8>,
+    pub double_quote: Option<bool>,
+    pub comment: Option<u8>,
+}
+
+/// Followed file state used to distinguish appended content from a rewrite
+struct Follow {
+    len: u64,
+    /// Last bytes of the followed content
+    fingerprint: Vec<u8>,
+}
+
+impl Follow {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let len = std::fs::metadata(path)?.len();
+        Ok(Self {
+            len,
+            fingerprint: fingerprint(path, len)?,
+        })
+    }
+
+    /// Check if content was only appended since last check
+    pub fn is_append(&mut self, path: &Path) -> io::Result<bool> {
+        let len = std::fs::metadata(path)?.len();
+        let is_append = len >= self.len && fingerprint(path, self.len)? == self.fingerprint;
+        *self = Self {
+            len,
+            fingerprint: fingerprint(path, len)?,
+        };
+        Ok(is_append)
+    }
+}
+
+/// Read the last bytes before len
+pub fn fingerprint(path: &Path, len: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let start = len.saturating_sub(FINGERPRINT_LEN);
+    file.seek(SeekFrom::Start(start))?;
+    let mut buff = Vec::new();
+    file.take(len - start).read_to_end(&mut buff)?;
+    Ok(buff)
+}
+
 pub struct Source {
     kind: SourceKind,
-    pub delimiter: u8,
+    opts: DialectOpts,
+    follow: Option<Follow>,
+    cache_dir: Option<PathBuf>,
+    cache: Option<IndexCache>,
+    mmap: bool,
+    /// Mapping shared by all readers
+    map: Option<Arc<Mmap>>,
+    pub dialect: Dialect,
     pub has_header: bool,
+    /// Confidence in the sniffed delimiter
+    pub confidence: Option<f32>,
+    /// Number of columns of the sniffed sample
+    pub nb_col: usize,
+    /// Stream sniffed before it wrote a whole sample
+    partial_sniff: bool,
     pub display_path: String,
 }
 
 impl Source {
-    pub fn new(filename: Option<PathBuf>) -> io::Result<(Self, CsvReader)> {
+    pub fn new(
+        filename: Option<PathBuf>,
+        opts: DialectOpts,
+        follow: bool,
+        cache_dir: Option<PathBuf>,
+        mmap: bool,
+    ) -> io::Result<(Self, CsvReader)> {
         let kind = if let Some(path) = filename {
-            let m_time = std::fs::metadata(&path)?.modified()?;
-            SourceKind::File {
-                path,
-                last: Instant::now(),
-                m_time,
-            }
+            SourceKind::from_path(path)?
         } else {
-            let mut stdin = std::io::stdin();
-            let mut tmp = tempfile::NamedTempFile::new()?;
-            std::io::copy(&mut stdin, &mut tmp)?;
-            SourceKind::Stdin { tmp }
+            // Spool stdin in the background to display its content while it is streaming
+            let tmp = tempfile::NamedTempFile::new()?;
+            let spool = Spool::new(None);
+            spool.spawn(std::io::stdin(), tmp.reopen()?);
+            SourceKind::Stdin { tmp, spool }
         };
         let display_path = kind.path().to_string();
-        let mut file = BufReader::new(kind.open()?);
-        let delimiter = sniff_delimiter(&mut file)?;
-        file.seek(SeekFrom::Start(0))?;
-        let mut rdr = CsvReader::new(file, delimiter);
-        let has_header = sniff_has_header(&mut rdr)?;
-        Ok((
-            Self {
-                kind,
-                delimiter,
-                has_header,
-                display_path,
-            },
-            rdr,
-        ))
+        let follow = match &kind {
+            SourceKind::File { path, .. } if follow => Some(Follow::new(path)?),
+            _ => None,
+        };
+        let (sniffed, partial_sniff) = kind.sniff_content(&opts)?;
+        let file = BufReader::new(kind.open()?);
+        let mut source = Self {
+            kind,
+            opts,
+            follow,
+            cache_dir,
+            cache: None,
+            mmap,
+            map: None,
+            dialect: sniffed.dialect,
+            has_header: sniffed.has_header,
+            confidence: sniffed.confidence,
+            nb_col: sniffed.nb_col,
+            partial_sniff,
+            display_path,
+        };
+        source.cache = source.open_cache();
+        source.map = source.open_map();
+        let rdr = source.csv_reader(file);
+        Ok((source, rdr))
+    }
+
+    /// Map plain files in memory when asked, followed files are read through a buffer as their
+    /// content changes while in use
+    fn open_map(&self) -> Option<Arc<Mmap>> {
+        match &self.kind {
+            SourceKind::File { path, .. } if self.mmap && self.follow.is_none() => {
+                let file = File::open(path).ok()?;
+                // SAFETY: the mapping is only valid while the file is not modified, a truncated
+                // file raises SIGBUS on access and a rewritten one changes mapped content before
+                // the watcher reloads it. Mapping is opt-in for files known to stay unchanged.
+                unsafe { Mmap::map(&file) }.ok().map(Arc::new)
+            }
+            _ => None,
+        }
+    }
+
+    fn csv_reader(&self, file: BufReader<Input>) -> CsvReader {
+        match &self.map {
+            Some(map) => CsvReader::mapped(map.clone(), &self.dialect),
+            None => CsvReader::new(file, &self.dialect),
+        }
+    }
+
+    fn open_cache(&self) -> Option<IndexCache> {
+        match (&self.kind, &self.cache_dir) {
+            (SourceKind::File { path, .. }, Some(dir)) => {
+                IndexCache::new(dir, path, &self.dialect, self.has_header)
+            }
+            _ => None,
+        }
     }
 
     pub fn refresh(&mut self) -> io::Result<CsvReader> {
-        let mut file = BufReader::new(self.kind.open()?);
-        self.delimiter = sniff_delimiter(&mut file)?;
-        file.seek(SeekFrom::Start(0))?;
-        let mut rdr = CsvReader::new(file, self.delimiter);
-        self.has_header = sniff_has_header(&mut rdr)?;
-        Ok(rdr)
+        if let SourceKind::Compressed { path, spool, .. } = &self.kind {
+            // Restart decompression from the new content and stop the previous one
+            let kind = SourceKind::from_path(path.clone())?;
+            spool.cancel();
+            self.kind = kind;
+        }
+        if let (Some(follow), SourceKind::File { path, .. }) = (&mut self.follow, &self.kind) {
+            *follow = Follow::new(path)?;
+        }
+        self.partial_sniff = false;
+        let (sniffed, partial_sniff) = self.kind.sniff_content(&self.opts)?;
+        let file = BufReader::new(self.kind.open()?);
+        self.partial_sniff = partial_sniff;
+        self.dialect = sniffed.dialect;
+        self.has_header = sniffed.has_header;
+        self.confidence = sniffed.confidence;
+        self.nb_col = sniffed.nb_col;
+        self.cache = self.open_cache();
+        self.map = self.open_map();
+        Ok(self.csv_reader(file))
     }
 
     pub fn reader(&self) -> io::Result<(CsvReader, NestedString)> {
-        let mut rdr = CsvReader::new(BufReader::new(self.kind.open()?), self.delimiter);
+        self.reader_from(|kind| kind.open())
+    }
+
+    /// Reader of the content spooled so far, that never waits for streamed data
+    pub fn written_reader(&self) -> io::Result<(CsvReader, NestedString)> {
+        self.reader_from(SourceKind::open_written)
+    }
+
+    fn reader_from(
+        &self,
+        open: impl FnOnce(&SourceKind) -> io::Result<Input>,
+    ) -> io::Result<(CsvReader, NestedString)> {
+        let mut rdr = match &self.map {
+            Some(map) => CsvReader::mapped(map.clone(), &self.dialect),
+            None => CsvReader::new(BufReader::new(open(&self.kind)?), &self.dialect),
+        };
         let mut headers = NestedString::new();
         if self.has_header {
             rdr.record(&mut headers)?;
@@ -97,9 +343,50 @@ impl Source {
         Ok((rdr, headers))
     }
 
+    /// Background spool if the source is compressed or streamed
+    pub fn spool(&self) -> Option<Spool> {
+        match &self.kind {
+            SourceKind::Compressed { spool, .. } | SourceKind::Stdin { spool, .. } => {
+                Some(spool.clone())
+            }
+            SourceKind::File { .. } => None,
+        }
+    }
+
+    /// Index cache if enabled for this source
+    pub fn cache(&self) -> Option<IndexCache> {
+        self.cache.clone()
+    }
+
+    /// Check if appended content is indexed as it comes
+    pub fn is_following(&self) -> bool {
+        self.follow.is_some()
+    }
+
+    /// Check if a stream sniffed on partial content has now written a whole sample
+    pub fn should_resniff(&self) -> bool {
+        match &self.kind {
+            SourceKind::Stdin { spool, .. } if self.partial_sniff => {
+                spool.is_done() || spool.written() >= SAMPLE_LEN as u64
+            }
+            _ => false,
+        }
+    }
+
+    /// Check if the source is a stream that is still open
+    pub fn is_streaming(&self) -> bool {
+        match &self.kind {
+            SourceKind::Stdin { spool, .. } => !spool.is_done(),
+            SourceKind::File { .. } | SourceKind::Compressed { .. } => false,
+        }
+    }
+
     pub fn check_dirty(&mut self) -> std::io::Result<bool> {
-        Ok(match &mut self.kind {
-            SourceKind::File { path, last, m_time } => {
+        let dirty = match &mut self.kind {
+            SourceKind::File { path, last, m_time }
+            | SourceKind::Compressed {
+                path, last, m_time, ..
+            } => {
                 if last.elapsed() < WATCHER_POOL {
                     false
                 } else {
@@ -107,87 +394,23 @@ impl Source {
                     let new_m_time = std::fs::metadata(&path)?.modified()?;
                     if new_m_time != *m_time {
                         *m_time = new_m_time;
-                        true
+                        // Appended content is already handled by the indexer
+                        match &mut self.follow {
+                            Some(follow) => !follow.is_append(path)?,
+                            None => true,
+                        }
                     } else {
                         false
                     }
                 }
             }
             SourceKind::Stdin { .. } => false,
-        })
-    }
-}
-
-/// Guess the csv delimiter from the first line
-fn sniff_delimiter(file: &mut BufReader<File>) -> io::Result<u8> {
-    const DELIMITER: [u8; 5] = [b',', b';', b':', b'|', b'_'];
-    let mut counter = [0; DELIMITER.len()];
-
-    'main: loop {
-        let buff = file.fill_buf()?;
-        if buff.is_empty() {
-            break 'main;
-        }
-        for c in buff {
-            if *c == b'\n' {
-                break 'main;
-            }
-            // Count occurrence of delimiter char
-            if let Some((count, _)) = counter.iter_mut().zip(DELIMITER).find(|(_, d)| d == c) {
-                *count += 1;
-            }
-        }
-        let amount = buff.len();
-        file.consume(amount);
-    }
-    // Return most used delimiter or ',' by default
-    Ok(counter
-        .iter()
-        .zip(DELIMITER)
-        .max_by_key(|(c, _)| *c)
-        .map(|(_, d)| d)
-        .unwrap_or(DELIMITER[0]))
-}
-
-/// Guess the csv delimiter from the first line
-fn sniff_has_header(rdr: &mut CsvReader) -> io::Result<bool> {
-    let mut record = NestedString::new();
-    rdr.record(&mut record)?; // Read headers
-    let mut tys = Vec::with_capacity(record.len());
-    let mut found_empty = false;
-    for field in record.iter() {
-        if field.is_empty() {
-            if !found_empty {
-                // Last row can be a fake one
-                found_empty = true;
-            } else {
-                // header should not be empty
-                return Ok(false);
+        };
+        if dirty {
+            if let Some(cache) = &self.cache {
+                cache.invalidate_stale();
             }
         }
-        tys.push(Ty::guess(field));
-    }
-
-    let all_str = tys.iter().all(|b| b.is_str());
-    rdr.record(&mut record)?; // Read first supposed data
-    let mut all_same = true;
-    for (i, field) in record.iter().enumerate() {
-        let ty = Ty::guess(field);
-        // typical headers are all string and a data column are not
-        if all_str && !ty.is_str() {
-            return Ok(true);
-        }
-
-        if ty != tys[i] {
-            all_same = false;
-        }
+        Ok(dirty)
     }
-
-    // typically data column have coherent type
-    if all_same && !all_str {
-        return Ok(false);
-    }
-
-    // default CSV commonly have headers
-    Ok(false)
 }
@@ -0,0 +1,198 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering::Relaxed},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+const SPOOL_BUF_LEN: usize = 64 * 1024;
+const TAIL_POOL: Duration = Duration::from_millis(50);
+const SAMPLE_WAIT: Duration = Duration::from_millis(500);
+
+struct State {
+    /// Number of bytes written in the spool file
+    written: AtomicU64,
+    /// Number of bytes consumed from the raw input
+    consumed: AtomicU64,
+    /// Raw input length if known
+    total: Option<u64>,
+    done: AtomicBool,
+    /// Copy should stop as the content is no longer used
+    canceled: AtomicBool,
+    /// Error that ended the copy before the end of the input
+    error: Mutex<Option<String>>,
+}
+
+/// Copy a stream into a file in the background so its content can be read while it is still
+/// being written
+#[derive(Clone)]
+pub struct Spool(Arc<State>);
+
+impl Spool {
+    pub fn new(total: Option<u64>) -> Self {
+        Self(Arc::new(State {
+            written: AtomicU64::new(0),
+            consumed: AtomicU64::new(0),
+            total,
+            done: AtomicBool::new(false),
+            canceled: AtomicBool::new(false),
+            error: Mutex::new(None),
+        }))
+    }
+
+    /// Wrap the raw input to track the number of consumed bytes
+    pub fn track<R: Read>(&self, inner: R) -> Tracked<R> {
+        Tracked {
+            inner,
+            spool: self.clone(),
+        }
+    }
+
+    /// Copy the input content into the spool file from a background thread
+    pub fn spawn(&self, mut input: impl Read + Send + 'static, mut out: File) {
+        let spool = self.clone();
+        thread::spawn(move || {
+            let mut buff = vec![0; SPOOL_BUF_LEN];
+            let error = loop {
+                if spool.0.canceled.load(Relaxed) {
+                    break None;
+                }
+                match input.read(&mut buff) {
+                    Ok(0) => break None,
+                    Ok(amount) => {
+                        if let Err(e) = out.write_all(&buff[..amount]) {
+                            break Some(format!("Spool write failed: {e}"));
+                        }
+                        spool.0.written.fetch_add(amount as u64, Relaxed);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    // Corrupted or truncated compressed content
+                    Err(e) => break Some(format!("Input read failed: {e}")),
+                }
+            };
+            *spool.0.error.lock() = error;
+            spool.0.done.store(true, Relaxed);
+        });
+    }
+
+    /// Stop the copy after the current read, readers then see the content written so far
+    pub fn cancel(&self) {
+        self.0.canceled.store(true, Relaxed);
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.0.done.load(Relaxed)
+    }
+
+    /// Number of bytes written in the spool file
+    pub fn written(&self) -> u64 {
+        self.0.written.load(Relaxed)
+    }
+
+    /// Error that ended the copy, the content is then incomplete
+    pub fn error(&self) -> Option<String> {
+        self.0.error.lock().clone()
+    }
+
+    /// Progress of a reader that read nb_read bytes of the spool file, relative to the raw input
+    /// if its length is known
+    pub fn progress(&self, nb_read: u64) -> u8 {
+        let written = self.0.written.load(Relaxed).max(1) as f64;
+        let read = (nb_read as f64 / written).min(1.);
+        match self.0.total {
+            Some(total) => {
+                let consumed = self.0.consumed.load(Relaxed) as f64;
+                (consumed / total.max(1) as f64 * read * 100.) as u8
+            }
+            None => (read * 100.) as u8,
+        }
+    }
+}
+
+/// Raw input wrapper counting consumed bytes
+pub struct Tracked<R: Read> {
+    inner: R,
+    spool: Spool,
+}
+
+impl<R: Read> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let amount = self.inner.read(buf)?;
+        self.spool.0.consumed.fetch_add(amount as u64, Relaxed);
+        Ok(amount)
+    }
+}
+
+/// A file that may still be written by a spool, reads wait for more data until the spool is done
+pub struct Input {
+    file: File,
+    spool: Option<Spool>,
+}
+
+impl Input {
+    pub fn file(file: File) -> Self {
+        Self { file, spool: None }
+    }
+
+    pub fn spooled(file: File, spool: Spool) -> Self {
+        Self {
+            file,
+            spool: Some(spool),
+        }
+    }
+
+    pub fn len(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Read a sample of up to len bytes, returning early if the spool is stalling, and whether
+    /// the sample contains the whole content
+    pub fn sample(&mut self, len: usize) -> io::Result<(Vec<u8>, bool)> {
+        let start = Instant::now();
+        let mut sample = vec![0; len];
+        let mut filled = 0;
+        let is_complete = loop {
+            let done = self.spool.as_ref().map(|s| s.is_done()).unwrap_or(true);
+            let amount = self.file.read(&mut sample[filled..])?;
+            filled += amount;
+            if filled == len {
+                break false;
+            } else if amount == 0 {
+                if done {
+                    break true;
+                } else if filled > 0 && start.elapsed() > SAMPLE_WAIT {
+                    break false;
+                }
+                thread::sleep(TAIL_POOL);
+            }
+        };
+        sample.truncate(filled);
+        Ok((sample, is_complete))
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // Check state before reading to not miss data written before completion
+            let done = self.spool.as_ref().map(|s| s.is_done()).unwrap_or(true);
+            let amount = self.file.read(buf)?;
+            if amount > 0 || done {
+                return Ok(amount);
+            }
+            thread::sleep(TAIL_POOL);
+        }
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
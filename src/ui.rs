@@ -1,5 +1,6 @@
 use std::ops::Range;
 
+use bstr::BString;
 use reedline::LineBuffer;
 use tui::{
     crossterm::event::KeyCode,
@@ -9,9 +10,10 @@ use tui::{
 };
 
 use crate::{
-    filter::{Highlighter, Style},
+    filter::{Completion, Error, Expect, Highlighter, Style},
     fmt::Fmt,
     prompt::{Prompt, PromptCmd},
+    reader::NestedString,
     style, Nav,
 };
 
@@ -124,10 +126,21 @@ impl Navigator {
     }
 }
 
+/// Candidates cycled by successive completions
+struct Cycle {
+    /// Span of the inserted candidate
+    span: Range<usize>,
+    candidates: Vec<String>,
+    idx: usize,
+}
+
 pub struct FilterPrompt {
     prompt: Prompt,
     offset: usize,
-    err: Option<(Range<usize>, &'static str)>,
+    err: Option<Error>,
+    cycle: Option<Cycle>,
+    /// Completion waiting for values to be sampled
+    waiting: bool,
 }
 
 impl FilterPrompt {
@@ -136,11 +149,15 @@ impl FilterPrompt {
             prompt: Prompt::new(),
             offset: 0,
             err: None,
+            cycle: None,
+            waiting: false,
         }
     }
 
     pub fn on_key(&mut self, code: KeyCode) -> (&str, bool) {
         self.err = None;
+        self.cycle = None;
+        self.waiting = false;
         match code {
             KeyCode::Char(c) => {
                 self.prompt.exec(PromptCmd::Write(c));
@@ -162,22 +179,85 @@ impl FilterPrompt {
         (str, false)
     }
 
+    /// Complete the token at the cursor or replace the previous completion with the next
+    /// candidate, values are the frequent contents of a column or of the current one and wait
+    /// until they are sampled when missing
+    pub fn complete(
+        &mut self,
+        headers: &NestedString,
+        nb_col: usize,
+        values: impl FnOnce(Option<usize>) -> Option<Vec<BString>>,
+    ) -> &str {
+        self.err = None;
+        self.waiting = false;
+        if let Some(cycle) = &mut self.cycle {
+            cycle.idx = (cycle.idx + 1) % cycle.candidates.len();
+            let candidate = cycle.candidates[cycle.idx].clone();
+            let span = cycle.span.clone();
+            cycle.span = span.start..span.start + candidate.len();
+            self.prompt.exec(PromptCmd::Replace(span, candidate));
+        } else {
+            let (str, cursor) = self.prompt.state();
+            let completion = Completion::at(str, cursor, headers, nb_col);
+            let values = match completion.expect {
+                Expect::Value(col) => values(col),
+                Expect::Operand | Expect::Operator => Some(Vec::new()),
+            };
+            let candidates = match &values {
+                Some(values) => completion.candidates(str, headers, nb_col, values),
+                None => Vec::new(),
+            };
+            self.waiting = values.is_none();
+            if let Some(candidate) = candidates.first().cloned() {
+                let span = completion.span.start..completion.span.start + candidate.len();
+                self.prompt
+                    .exec(PromptCmd::Replace(completion.span, candidate));
+                if candidates.len() > 1 {
+                    self.cycle = Some(Cycle {
+                        span,
+                        candidates,
+                        idx: 0,
+                    });
+                }
+            }
+        }
+        let (str, _) = self.prompt.state();
+        str
+    }
+
     pub fn on_compile(&mut self) {
         self.prompt.exec(PromptCmd::New(true));
     }
 
-    pub fn on_error(&mut self, err: (Range<usize>, &'static str), apply: bool) {
+    /// Forget the pending completion when the prompt is closed
+    pub fn on_close(&mut self) {
+        self.cycle = None;
+        self.waiting = false;
+    }
+
+    /// Check if a completion waits for values
+    pub fn is_waiting(&self) -> bool {
+        self.waiting
+    }
+
+    pub fn on_error(&mut self, err: Error, apply: bool) {
         if apply {
             self.prompt.exec(PromptCmd::Jump(err.0.start))
         }
         self.err.replace(err);
     }
 
-    pub fn draw_prompt(&mut self, c: &mut Canvas) {
+    pub fn draw_prompt(
+        &mut self,
+        c: &mut Canvas,
+        headers: &NestedString,
+        nb_col: usize,
+        refine: bool,
+    ) {
         let mut l = c.btm();
-        l.draw("$ ", none().fg(Color::DarkGrey));
+        l.draw(if refine { "& " } else { "$ " }, none().fg(Color::DarkGrey));
         let (str, cursor) = self.prompt.state();
-        let mut highlighter = Highlighter::new(str);
+        let mut highlighter = Highlighter::new(str, headers, nb_col);
         let mut pending_cursor = true;
 
         let mut w = l.width();
@@ -241,6 +321,7 @@ impl FilterPrompt {
                     Style::Str => none().fg(Color::Green),
                     Style::Regex => none().fg(Color::Magenta),
                     Style::Action => none().fg(Color::Red),
+                    Style::Func => none().fg(Color::Cyan),
                 },
             );
         }
@@ -289,11 +370,31 @@ impl FilterPrompt {
                     l.draw(format_args!("◂ {msg}"), none().fg(Color::Red));
                 }
             }
+        } else if self.waiting {
+            c.btm()
+                .draw("  loading values…", none().fg(Color::DarkGrey));
+        } else if let Some(cycle) = &self.cycle {
+            // Draw completion candidates
+            let mut l = c.btm();
+            l.draw("  ", none());
+            for (i, candidate) in cycle.candidates.iter().enumerate() {
+                let style = if i == cycle.idx {
+                    style::selected()
+                } else {
+                    none().fg(Color::DarkGrey)
+                };
+                l.draw(candidate, style);
+                l.draw(" ", none());
+            }
+        } else if let Some(hint) = Completion::at(str, cursor, headers, nb_col).hint {
+            // Draw signature hint
+            c.btm()
+                .draw(format_args!("  {hint}"), none().fg(Color::DarkGrey));
         }
     }
 
-    pub fn draw_status(l: &mut Line, filter: &str) {
-        let mut highlighter = Highlighter::new(filter);
+    pub fn draw_status(l: &mut Line, filter: &str, headers: &NestedString, nb_col: usize) {
+        let mut highlighter = Highlighter::new(filter, headers, nb_col);
         for (i, c) in filter.char_indices() {
             if l.width() == 0 {
                 return;
@@ -307,6 +408,7 @@ impl FilterPrompt {
                     Style::Str => none().fg(Color::Green),
                     Style::Regex => none().fg(Color::Magenta),
                     Style::Action => none().fg(Color::Red),
+                    Style::Func => none().fg(Color::Cyan),
                 },
             );
         }